@@ -1,11 +1,321 @@
 #![no_std]
 #![no_main]
 
-use ministd::{def_main, io::Error};
+extern crate alloc;
 
+use alloc::{collections::BTreeSet, format, vec::Vec};
+use lilium_sys::{
+    sys::fs::{ACCESS_EXECUTE, ACCESS_READ, ACCESS_WRITE},
+    time::Duration,
+};
+use ministd::{
+    def_main, eprintln,
+    fs::{DirEntry, File, FileType, Metadata, OpenOptions, ReadDir},
+    io::{Error, ErrorKind},
+    print, println,
+};
 
-fn main() -> Result<(), Error> {
-    Ok(())
+struct Options {
+    all: bool,
+    long: bool,
+    recursive: bool,
+    max_depth: Option<usize>,
+}
+
+fn main() -> Result<i32, Error> {
+    let mut args = ministd::start::args();
+    let prg_name = args.next().unwrap();
+
+    let mut opts = Options {
+        all: false,
+        long: false,
+        recursive: false,
+        max_depth: None,
+    };
+    let mut paths = Vec::new();
+    let mut no_opts = false;
+
+    for arg in args {
+        match arg {
+            x if no_opts => paths.push(x),
+            "--" => no_opts = true,
+            "--help" => {
+                println!("Usage: {prg_name} [OPTION]... [FILE]...");
+                println!("Lists the contents of each directory (the current directory by default)");
+                println!("Options:");
+                println!("\t-a: Includes entries that begin with `.`");
+                println!("\t-l: Uses the long listing format");
+                println!("\t-R: Lists subdirectories recursively");
+                println!("\t--max-depth=N: Stops recursing N directories below each FILE");
+                println!("\t--help: Prints this message and exits");
+                println!("\t--version: Prints version information and exits");
+                return Ok(0);
+            }
+            "--version" => {
+                println!("ls (lilium-tools) v{}", core::env!("CARGO_PKG_VERSION"));
+                return Ok(0);
+            }
+            x if x.starts_with("--max-depth=") => {
+                let depth = x["--max-depth=".len()..]
+                    .parse()
+                    .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+                opts.max_depth = Some(depth);
+            }
+            x if x.starts_with("--") => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    alloc::format!("Unknown Option {x}"),
+                ));
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                for c in x[1..].chars() {
+                    match c {
+                        'a' => opts.all = true,
+                        'l' => opts.long = true,
+                        'R' => opts.recursive = true,
+                        c => {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                alloc::format!("Unknown Option -{c}"),
+                            ));
+                        }
+                    }
+                }
+            }
+            x => paths.push(x),
+        }
+    }
+
+    if paths.is_empty() {
+        paths.push(".");
+    }
+
+    let mut lister = Lister {
+        prg_name,
+        opts: &opts,
+        print_header: paths.len() > 1 || opts.recursive,
+        first: true,
+        ancestors: BTreeSet::new(),
+        status: 0,
+    };
+
+    for path in paths {
+        match dir_options().open(path) {
+            Ok(dir) => {
+                lister.list(path, dir, 0);
+            }
+            Err(e) => {
+                eprintln!("{prg_name}: cannot access {path}: {e}");
+                lister.status = 2;
+            }
+        }
+    }
+
+    Ok(lister.status)
+}
+
+struct Lister<'a> {
+    prg_name: &'a str,
+    opts: &'a Options,
+    print_header: bool,
+    first: bool,
+    /// The ids of the directories that the current directory is nested in, including itself.
+    ancestors: BTreeSet<(u64, u64)>,
+    status: i32,
+}
+
+impl Lister<'_> {
+    fn list(&mut self, path: &str, dir: File, depth: usize) {
+        let prg_name = self.prg_name;
+
+        let id = match dir.metadata() {
+            Ok(meta) => meta.id(),
+            Err(e) => {
+                eprintln!("{prg_name}: cannot access {path}: {e}");
+                self.status = 2;
+                return;
+            }
+        };
+        // Only a directory that contains itself is a loop, the same directory may be reached twice otherwise
+        if !self.ancestors.insert(id) {
+            let e =
+                Error::new_with_message(ErrorKind::FilesystemLoop, "Directory is inside itself");
+            eprintln!("{prg_name}: not listing {path}: {e}");
+            self.status = self.status.max(1);
+            return;
+        }
+        self.list_contents(path, dir, depth);
+        self.ancestors.remove(&id);
+    }
+
+    fn list_contents(&mut self, path: &str, dir: File, depth: usize) {
+        let prg_name = self.prg_name;
+
+        if self.print_header {
+            if !self.first {
+                println!();
+            }
+            println!("{path}:");
+        }
+        self.first = false;
+
+        let mut entries = Vec::new();
+        for entry in ReadDir::new(dir) {
+            match entry {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    eprintln!("{prg_name}: cannot read directory {path}: {e}");
+                    self.status = 2;
+                    return;
+                }
+            }
+        }
+        entries.sort_by(|a, b| a.name().cmp(b.name()));
+        entries.retain(|entry| self.opts.all || !entry.name().starts_with('.'));
+
+        if self.opts.long {
+            let metas = entries
+                .iter()
+                .map(|entry| match entry.metadata() {
+                    Ok(meta) => Some(meta),
+                    Err(e) => {
+                        eprintln!("{prg_name}: cannot access {path}/{}: {e}", entry.name());
+                        self.status = self.status.max(1);
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+            print_long(&entries, &metas);
+        } else {
+            for entry in &entries {
+                println!("{}", entry.name());
+            }
+        }
+
+        if !self.opts.recursive || self.opts.max_depth.is_some_and(|max| depth >= max) {
+            return;
+        }
+
+        for entry in &entries {
+            let name = entry.name();
+            if !entry.file_type().is_dir() || name == "." || name == ".." {
+                continue;
+            }
+
+            let sub_path = format!("{path}/{name}");
+            match entry.open(&dir_options()) {
+                Ok(sub) => self.list(&sub_path, sub, depth + 1),
+                Err(e) => {
+                    eprintln!("{prg_name}: cannot open directory {sub_path}: {e}");
+                    self.status = self.status.max(1);
+                }
+            }
+        }
+    }
+}
+
+fn dir_options() -> OpenOptions {
+    let mut opts = OpenOptions::new();
+    opts.read(true).directory(true);
+    opts
+}
+
+/// Prints `entries` in the long format.
+///
+/// Entries whose metadata could not be read are printed with placeholders.
+fn print_long(entries: &[DirEntry], metas: &[Option<Metadata>]) {
+    let size_width = metas
+        .iter()
+        .flatten()
+        .map(|meta| count_digits(meta.len()))
+        .max()
+        .unwrap_or(1);
+
+    for (entry, meta) in entries.iter().zip(metas) {
+        let name = entry.name();
+        match meta {
+            Some(meta) => {
+                print!(
+                    "{}{} {} {:>size_width$} ",
+                    file_type_char(meta.file_type()),
+                    AccessMode(meta.access()),
+                    meta.owner(),
+                    meta.len()
+                );
+                print_time(meta.modified());
+                println!(" {name}");
+            }
+            None => println!("???? {:36} {:>size_width$} {:16} {name}", "?", "?", "?"),
+        }
+    }
+}
+
+fn file_type_char(ty: FileType) -> char {
+    if ty.is_file() {
+        '-'
+    } else if ty.is_dir() {
+        'd'
+    } else if ty.is_symlink() {
+        'l'
+    } else if ty.is_device() {
+        'c'
+    } else if ty.is_fifo() {
+        'p'
+    } else if ty.is_socket() {
+        's'
+    } else {
+        '?'
+    }
+}
+
+/// Formats the access the current thread is granted on an object as `rwx`.
+struct AccessMode(u32);
+
+impl core::fmt::Display for AccessMode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use core::fmt::Write;
+        for (bit, c) in [
+            (ACCESS_READ, 'r'),
+            (ACCESS_WRITE, 'w'),
+            (ACCESS_EXECUTE, 'x'),
+        ] {
+            f.write_char(if self.0 & bit != 0 { c } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+fn count_digits(mut n: u64) -> usize {
+    let mut digits = 1;
+    while n >= 10 {
+        n /= 10;
+        digits += 1;
+    }
+    digits
+}
+
+/// Prints a timestamp relative to the Unix Epoch as `YYYY-MM-DD HH:MM` (UTC).
+fn print_time(time: Duration) {
+    let secs = time.seconds;
+    let days = secs.div_euclid(86400);
+    let secs_of_day = secs.rem_euclid(86400);
+
+    // Civil-from-days, see <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    print!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}",
+        secs_of_day / 3600,
+        (secs_of_day / 60) % 60
+    );
 }
 
 def_main!();
@@ -0,0 +1,131 @@
+use alloc::{format, string::String, vec::Vec};
+use ministd::fs::{self, OpenOptions, ReadDir};
+
+use crate::shell::{BUILTINS, Shell};
+
+/// Characters that separate words, besides whitespace.
+const SEPARATORS: &[char] = &[';', '|', '&', '<', '>', '(', ')'];
+
+/// Reserved words that are followed by a command.
+const COMMAND_KEYWORDS: &[&str] = &["if", "then", "elif", "else", "while", "until", "do", "{"];
+
+/// Characters that are escaped with `\` when a completion is inserted.
+const SPECIAL: &[char] = &[
+    ' ', '\t', '\'', '"', '\\', '$', '`', ';', '|', '&', '<', '>', '(', ')', '*', '?', '[', '#',
+];
+
+/// Finds the completions of the word at the end of `line`.
+///
+/// Returns where the word starts, and the sorted candidates to replace it with.
+/// A word in command position completes to a builtin, function or program in `PATH`,
+/// and any other word completes to a path relative to the current directory.
+pub fn complete(shell: &Shell, line: &str) -> (usize, Vec<String>) {
+    let start = line
+        .char_indices()
+        .rev()
+        .find(|&(i, c)| {
+            (c.is_whitespace() || SEPARATORS.contains(&c)) && !line[..i].ends_with('\\')
+        })
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let word = unescape(&line[start..]);
+
+    let before = line[..start].trim_end();
+    let command = !word.contains('/')
+        && (before.is_empty()
+            || before.ends_with(SEPARATORS)
+            || before
+                .rsplit(char::is_whitespace)
+                .next()
+                .is_some_and(|prev| COMMAND_KEYWORDS.contains(&prev)));
+
+    let mut candidates = if command {
+        commands(shell, &word)
+    } else {
+        paths(&word)
+    };
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates.into_iter().map(|c| escape(&c)).collect())
+}
+
+fn unescape(word: &str) -> String {
+    let mut out = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn escape(word: &str) -> String {
+    let mut out = String::new();
+    for c in word.chars() {
+        if SPECIAL.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// The builtins, functions, and programs in the directories of `PATH` that start with `prefix`.
+fn commands(shell: &Shell, prefix: &str) -> Vec<String> {
+    let mut names: Vec<String> = BUILTINS
+        .iter()
+        .copied()
+        .chain(shell.function_names())
+        .filter(|name| name.starts_with(prefix))
+        .map(String::from)
+        .collect();
+
+    shell.with_path_dirs(|dirs| {
+        for (_, dir) in dirs {
+            // The cached handle is shared, so each listing starts from a handle of its own
+            let Ok(dir) = OpenOptions::new()
+                .read(true)
+                .directory(true)
+                .open_at(dir.as_raw_handle(), ".")
+            else {
+                continue;
+            };
+            names.extend(
+                ReadDir::new(dir)
+                    .flatten()
+                    .filter(|entry| entry.name().starts_with(prefix) && !entry.file_type().is_dir())
+                    .map(|entry| entry.into_name()),
+            );
+        }
+    });
+    names
+}
+
+/// The paths that start with `prefix`, with a `/` after each directory.
+///
+/// Names starting with `.` are only included if the last component of `prefix` does.
+fn paths(prefix: &str) -> Vec<String> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(n) => prefix.split_at(n + 1),
+        None => ("", prefix),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| {
+            entry.name().starts_with(name)
+                && (name.starts_with('.') || !entry.name().starts_with('.'))
+        })
+        .map(|entry| {
+            let mut path = format!("{dir}{}", entry.name());
+            if entry.file_type().is_dir() {
+                path.push('/');
+            }
+            path
+        })
+        .collect()
+}
@@ -0,0 +1,342 @@
+use alloc::{format, string::String, vec::Vec};
+use genio::{Read, Write};
+use ministd::{
+    fs::{File, OpenOptions},
+    io::{self, ReadEx, stdin, stdout},
+};
+
+/// The lines that have been entered at the prompt, oldest first.
+#[derive(Default)]
+pub struct History {
+    entries: Vec<String>,
+    /// The file that new entries are appended to.
+    path: Option<String>,
+}
+
+impl History {
+    /// Loads the history saved in `path`, which new entries are then appended to.
+    ///
+    /// A file that can't be read is treated as empty.
+    pub fn load(path: String) -> Self {
+        let mut src = String::new();
+        if let Ok(mut file) = File::open(&path) {
+            let _ = ReadEx::read_to_string(&mut file, &mut src);
+        }
+        Self {
+            entries: src.lines().map(String::from).collect(),
+            path: Some(path),
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Adds `line` to the history, unless it is blank or the same as the previous entry.
+    ///
+    /// Errors writing the history file are ignored.
+    pub fn push(&mut self, line: &str) {
+        let line = line.trim_end_matches('\n');
+        if line.trim().is_empty() || self.entries.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.entries.push(String::from(line));
+
+        if let Some(path) = &self.path {
+            let _ = OpenOptions::new()
+                .write(true)
+                .append(true)
+                .create(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(format!("{line}\n").as_bytes()));
+        }
+    }
+}
+
+/// A key, after escape sequences have been decoded.
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// Ctrl-D, which ends the input on an empty line.
+    Eof,
+    /// Ctrl-U
+    KillToStart,
+    /// Ctrl-W
+    KillWord,
+    Tab,
+    Ignored,
+}
+
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut buf = [0];
+    match stdin().read(&mut buf)? {
+        0 => Ok(None),
+        _ => Ok(Some(buf[0])),
+    }
+}
+
+/// Reads one key press, returning `None` at the end of the input.
+fn read_key() -> io::Result<Option<Key>> {
+    let Some(b) = read_byte()? else {
+        return Ok(None);
+    };
+    let key = match b {
+        b'\r' | b'\n' => Key::Enter,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x08 | 0x7f => Key::Backspace,
+        b'\t' => Key::Tab,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x15 => Key::KillToStart,
+        0x17 => Key::KillWord,
+        0x1b => read_escape()?,
+        0x00..0x20 => Key::Ignored,
+        0x20..0x80 => Key::Char(b as char),
+        _ => {
+            // The first byte of a UTF-8 sequence gives its length
+            let len = b.leading_ones() as usize;
+            let mut buf = [b, 0, 0, 0];
+            for byte in buf.iter_mut().take(len.min(4)).skip(1) {
+                *byte = read_byte()?.unwrap_or(0);
+            }
+            match core::str::from_utf8(&buf[..len.clamp(1, 4)]) {
+                Ok(s) => s.chars().next().map_or(Key::Ignored, Key::Char),
+                Err(_) => Key::Ignored,
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+/// Decodes the rest of an escape sequence, like `ESC [ A` for the up arrow.
+fn read_escape() -> io::Result<Key> {
+    if !matches!(read_byte()?, Some(b'[' | b'O')) {
+        return Ok(Key::Ignored);
+    }
+    let key = match read_byte()? {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        // `ESC [ N ~`, like `ESC [ 3 ~` for delete
+        Some(n @ b'0'..=b'9') => {
+            let mut n = u32::from(n - b'0');
+            loop {
+                match read_byte()? {
+                    Some(d @ b'0'..=b'9') => n = n * 10 + u32::from(d - b'0'),
+                    Some(b'~') => break,
+                    _ => return Ok(Key::Ignored),
+                }
+            }
+            match n {
+                1 | 7 => Key::Home,
+                3 => Key::Delete,
+                4 | 8 => Key::End,
+                _ => Key::Ignored,
+            }
+        }
+        _ => Key::Ignored,
+    };
+    Ok(key)
+}
+
+/// Finds the completions of the word at the end of the text before the cursor.
+///
+/// Returns the byte offset where the word starts, and the candidates to replace it with.
+pub type Complete<'a> = dyn Fn(&str) -> (usize, Vec<String>) + 'a;
+
+/// The longest prefix that all of `words` share.
+fn common_prefix(words: &[String]) -> &str {
+    let mut prefix = words.first().map_or("", String::as_str);
+    for word in words {
+        let len = prefix
+            .char_indices()
+            .zip(word.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(prefix.len().min(word.len()), |((i, _), _)| i);
+        prefix = &prefix[..len];
+    }
+    prefix
+}
+
+/// Prints `words` in columns below the line, ordered down each column like `ls` does.
+fn print_columns(words: &[String]) -> io::Result<()> {
+    // The width of the terminal isn't known, so assume the usual
+    const WIDTH: usize = 80;
+    let col_width = words.iter().map(|w| w.chars().count()).max().unwrap_or(0) + 2;
+    let cols = (WIDTH / col_width).max(1);
+    let rows = words.len().div_ceil(cols);
+
+    let mut out = String::from("\r\n");
+    for row in 0..rows {
+        for word in words.iter().skip(row).step_by(rows) {
+            out.push_str(word);
+            out.extend(core::iter::repeat_n(' ', col_width - word.chars().count()));
+        }
+        out.push_str("\r\n");
+    }
+    stdout().write_all(out.as_bytes())
+}
+
+/// The line that is being edited.
+struct Line<'h> {
+    prompt: &'h str,
+    chars: Vec<char>,
+    /// The index in `chars` that the cursor is before.
+    cursor: usize,
+    history: &'h History,
+    /// The history entry being shown, or `history.entries().len()` for the new line.
+    entry: usize,
+    /// The new line, while a history entry is being shown.
+    saved: Vec<char>,
+}
+
+impl<'h> Line<'h> {
+    /// Redraws the prompt and the line, and puts the cursor in place.
+    fn redraw(&self) -> io::Result<()> {
+        let mut out = String::from("\r");
+        out.push_str(self.prompt);
+        out.extend(&self.chars);
+        out.push_str("\x1b[K");
+        let back = self.chars.len() - self.cursor;
+        if back > 0 {
+            out.push_str(&format!("\x1b[{back}D"));
+        }
+        let mut stdout = stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+
+    /// Shows history entry `entry` in place of the line.
+    fn show_entry(&mut self, entry: usize) {
+        let entries = self.history.entries();
+        if self.entry == entries.len() {
+            self.saved = core::mem::take(&mut self.chars);
+        }
+        self.entry = entry;
+        self.chars = match entries.get(entry) {
+            Some(line) => line.chars().collect(),
+            None => core::mem::take(&mut self.saved),
+        };
+        self.cursor = self.chars.len();
+    }
+
+    /// Completes the word before the cursor, or lists the candidates if there are several and none is longer than the word.
+    fn complete(&mut self, complete: &Complete) -> io::Result<()> {
+        let before: String = self.chars[..self.cursor].iter().collect();
+        let (start, candidates) = complete(&before);
+        let start = before[..start].chars().count();
+
+        let replacement = match &candidates[..] {
+            [] => return Ok(()),
+            [only] if only.ends_with('/') => only.clone(),
+            [only] => format!("{only} "),
+            _ => String::from(common_prefix(&candidates)),
+        };
+        let len = replacement.chars().count();
+        if candidates.len() > 1 && len <= self.cursor - start {
+            return print_columns(&candidates);
+        }
+
+        self.chars.splice(start..self.cursor, replacement.chars());
+        self.cursor = start + len;
+        Ok(())
+    }
+
+    /// Applies the editing key `key`.
+    fn edit(&mut self, key: Key) {
+        match key {
+            Key::Char(c) => {
+                self.chars.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.chars.remove(self.cursor);
+            }
+            Key::Delete if self.cursor < self.chars.len() => {
+                self.chars.remove(self.cursor);
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.chars.len(),
+            Key::Up if self.entry > 0 => self.show_entry(self.entry - 1),
+            Key::Down if self.entry < self.history.entries().len() => {
+                self.show_entry(self.entry + 1)
+            }
+            Key::KillToStart => {
+                self.chars.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::KillWord => {
+                // Like other shells, removes any whitespace before the cursor, then the word before that
+                let mut start = self.cursor;
+                while start > 0 && self.chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                while start > 0 && !self.chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                self.chars.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Reads a line from the terminal on stdin, after printing `prompt`, with line editing and `history`.
+///
+/// Tab completes the word before the cursor with `complete`.
+/// Returns the line with a trailing newline, or `None` if Ctrl-D is pressed on an empty line or the input ends.
+pub fn read_line(
+    prompt: &str,
+    history: &History,
+    complete: &Complete,
+) -> io::Result<Option<String>> {
+    let raw = stdin().enable_raw_mode()?;
+    let mut line = Line {
+        prompt,
+        chars: Vec::new(),
+        cursor: 0,
+        history,
+        entry: history.entries().len(),
+        saved: Vec::new(),
+    };
+    line.redraw()?;
+
+    let res = loop {
+        match read_key()? {
+            None => break None,
+            Some(Key::Eof) if line.chars.is_empty() => break None,
+            Some(Key::Eof) => line.edit(Key::Delete),
+            Some(Key::Tab) => line.complete(complete)?,
+            Some(Key::Enter) => {
+                let mut s: String = line.chars.into_iter().collect();
+                s.push('\n');
+                break Some(s);
+            }
+            Some(key) => line.edit(key),
+        }
+        line.redraw()?;
+    };
+
+    drop(raw);
+    stdout().write_all(b"\r\n")?;
+    Ok(res)
+}
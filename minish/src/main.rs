@@ -8,50 +8,139 @@ unsafe extern "C" {
     pub safe fn exit(x: i32) -> !;
 }
 
+mod complete;
+mod editor;
+mod pattern;
 mod shell;
 
 use core::panic::PanicInfo;
 
-use alloc::string::String;
+use alloc::{format, string::String};
 use error_repr::Error;
+use genio::Write;
 use lilium_sys::{
     sys::except::{ExceptionStatusInfo, UnmanagedException},
     uuid::parse_uuid,
 };
 use ministd::io::{self, BufReadEx, BufReader, ReadToStringError, stderr, stdin};
 use ministd::{eprintln, print, println};
-use shell::{parse_shell, split_shell};
+use shell::{ParseError, parse_shell, read_script, split_shell};
 
-use crate::shell::exec_line;
+use crate::{editor::History, shell::Shell};
 
 fn main() -> io::Result<i32> {
+    let mut args = ministd::start::args();
+    let prg_name = args.next().unwrap_or("minish");
+    let mut shell = Shell::new();
+
+    match args.next() {
+        Some("-c") => {
+            let Some(src) = args.next() else {
+                eprintln!("{prg_name}: -c: option requires an argument");
+                return Ok(2);
+            };
+            if let Some(name) = args.next() {
+                shell.set_name(name);
+            }
+            shell.set_params(args.map(String::from).collect());
+            Ok(shell.exec_source(src))
+        }
+        Some("-s") => {
+            shell.set_params(args.map(String::from).collect());
+            run(&mut shell, false)
+        }
+        Some(opt) if opt.starts_with('-') => {
+            eprintln!("{prg_name}: {opt}: invalid option");
+            eprintln!(
+                "Usage: {prg_name} [-c COMMAND [NAME [ARG]...] | -s [ARG]... | FILE [ARG]...]"
+            );
+            Ok(2)
+        }
+        Some(path) => {
+            let src = match read_script(path) {
+                Ok(src) => src,
+                Err(e) => {
+                    eprintln!("{prg_name}: {path}: {e}");
+                    return Ok(127);
+                }
+            };
+            shell.set_name(path);
+            shell.set_params(args.map(String::from).collect());
+            Ok(shell.exec_source(&src))
+        }
+        None => {
+            shell.set_interactive(true);
+            if let Some(home) = shell.var("HOME") {
+                *shell.history_mut() = History::load(format!("{home}/.minish_history"));
+            }
+            run(&mut shell, true)
+        }
+    }
+}
+
+/// Runs the commands read from stdin line by line, and returns the status of the last one.
+///
+/// In interactive mode, prompts for each line and echoes each command, and lines are added to the history.
+/// If stdin is also a terminal, lines are read with the line editor.
+/// Lines are collected until they form a complete command, with a continuation prompt in between.
+fn run(shell: &mut Shell, interactive: bool) -> io::Result<i32> {
     let mut line = String::new();
     let mut reader = BufReader::new(stdin());
+    let edit = interactive && stdin().is_terminal();
     loop {
-        line.clear();
-        print!("# ");
-        let n = reader.read_line(&mut line).map_err(|e| match e {
-            ReadToStringError::Read(r) => r,
-            ReadToStringError::InvalidUtf8 => {
-                Error::new_with_message(io::ErrorKind::InvalidData, "Invalid UTF-8 Text")
+        if interactive && line.is_empty() {
+            shell.notify_jobs();
+        }
+        let prompt = if line.is_empty() { "# " } else { "> " };
+
+        let n = if edit {
+            let complete = |text: &str| complete::complete(shell, text);
+            match editor::read_line(prompt, shell.history(), &complete)? {
+                Some(input) => {
+                    line.push_str(&input);
+                    input.len()
+                }
+                None => 0,
+            }
+        } else {
+            if interactive {
+                print!("{prompt}");
+                io::stdout().flush()?;
             }
-        })?;
+            reader.read_line(&mut line).map_err(|e| match e {
+                ReadToStringError::Read(r) => r,
+                ReadToStringError::InvalidUtf8 => {
+                    Error::new_with_message(io::ErrorKind::InvalidData, "Invalid UTF-8 Text")
+                }
+            })?
+        };
         if n == 0 {
-            println!("exit");
-            return Ok(0);
+            if !line.is_empty() {
+                eprintln!("{}: {}", shell.name(), ParseError::Incomplete);
+            }
+            if interactive {
+                println!("exit");
+            }
+            return Ok(shell.status());
         }
 
-        let line = parse_shell(split_shell(&line));
-
-        if let Some(cmd) = &line.command {
-            eprintln!("{line}");
-            match exec_line(&line) {
-                Ok(_) => {}
-                Err(e) => {
-                    println!("Error spawning {cmd}: {e}")
+        let res = parse_shell(split_shell(&line));
+        // The whole command goes into the history once it's complete, including its continuation lines
+        if interactive && !matches!(res, Err(ParseError::Incomplete)) {
+            shell.history_mut().push(&line);
+        }
+        match res {
+            Ok(list) if !list.is_empty() => {
+                if interactive {
+                    eprintln!("{list}");
                 }
+                shell.exec_list(&list);
             }
+            Ok(_) => {}
+            Err(ParseError::Incomplete) => continue,
+            Err(e) => eprintln!("{}: {e}", shell.name()),
         }
+        line.clear();
     }
 }
 
@@ -0,0 +1,164 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use ministd::fs;
+
+/// Appends `s` to `out`, escaping it so that it only matches itself.
+pub fn escape_into(s: &str, out: &mut String) {
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+/// Whether `pattern` has any wildcards that are not escaped.
+pub fn has_wildcards(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' | '?' | '[' => return true,
+            '\\' => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Removes the escapes from `pattern`, giving the text it matches if it has no wildcards.
+fn unescape(pattern: &str) -> String {
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Finds the paths that match `pattern`, relative to the current directory unless it starts with `/`, in sorted order.
+///
+/// Each component of the path is matched against the entries of a directory.
+/// Like in other shells, names starting with `.` only match a pattern that starts with `.`,
+/// and `.` and `..` never match.
+pub fn glob(pattern: &str) -> Vec<String> {
+    let (root, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => ("/", rest),
+        None => ("", pattern),
+    };
+    let mut paths = vec![String::from(root)];
+
+    let mut components = rest.split('/').peekable();
+    while let Some(component) = components.next() {
+        let sep = if components.peek().is_some() { "/" } else { "" };
+        let mut next = Vec::new();
+        for dir in &paths {
+            if !has_wildcards(component) {
+                let path = format!("{dir}{}{sep}", unescape(component));
+                if fs::symlink_metadata(&path).is_ok() {
+                    next.push(path);
+                }
+                continue;
+            }
+
+            let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.name();
+                if matches!(name, "." | "..")
+                    || (name.starts_with('.') && !component.starts_with('.'))
+                    || !matches(component, name)
+                {
+                    continue;
+                }
+                next.push(format!("{dir}{name}{sep}"));
+            }
+        }
+        paths = next;
+    }
+
+    paths.sort();
+    paths
+}
+
+/// Whether the whole of `s` matches the shell pattern `pattern`.
+///
+/// `*` matches any string, `?` matches any character, and `[...]` matches any character in the brackets
+/// (or any character not in them, if they start with `!` or `^`). `\` makes the following character literal.
+pub fn matches(pattern: &str, s: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+    let (mut pi, mut si) = (0, 0);
+    // Where to resume if the rest fails to match: the last `*`, and how much of `s` it has consumed
+    let mut backtrack = None;
+
+    while si < s.len() {
+        let step = match p.get(pi) {
+            Some('*') => {
+                backtrack = Some((pi, si));
+                pi += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match match_bracket(&p[pi..], s[si]) {
+                Some((matched, len)) => matched.then_some(len),
+                None => (s[si] == '[').then_some(1),
+            },
+            Some('\\') if pi + 1 < p.len() => (p[pi + 1] == s[si]).then_some(2),
+            Some(&c) => (c == s[si]).then_some(1),
+            None => None,
+        };
+
+        match (step, backtrack) {
+            (Some(len), _) => {
+                pi += len;
+                si += 1;
+            }
+            (None, Some((star, consumed))) => {
+                pi = star + 1;
+                si = consumed + 1;
+                backtrack = Some((star, consumed + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the bracket expression at the start of `p`, like `[a-z_]` or `[!0-9]`.
+///
+/// Returns whether `c` matched and the length of the expression, or `None` if the `[` is never closed.
+fn match_bracket(p: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(p.get(i), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+
+    // A `]` right after the `[` is part of the set
+    let start = i;
+    let mut matched = false;
+    loop {
+        let lo = *p.get(i)?;
+        if lo == ']' && i != start {
+            break;
+        }
+        match (p.get(i + 1), p.get(i + 2)) {
+            (Some('-'), Some(&hi)) if hi != ']' => {
+                matched |= (lo..=hi).contains(&c);
+                i += 3;
+            }
+            _ => {
+                matched |= lo == c;
+                i += 1;
+            }
+        }
+    }
+
+    Some((matched != negate, i + 1))
+}
@@ -1,27 +1,19 @@
-use core::{cell::LazyCell, ops::Deref};
-
-use alloc::{borrow::Cow, string::String, vec::Vec};
-use bytemuck::Zeroable;
-use lilium_sys::sys::{
-    fs::{ACCESS_READ, FileHandle, FileOpenOptions, OP_DIRECTORY_ACCESS, OpenFile},
-    handle::HandlePtr,
-    io::MODE_BLOCKING,
-    kstr::{KCSlice, KStrCPtr},
-    option::ExtendedOptionHead,
-    process::{
-        CREATE_PROCESS_OPTION_ARGS, CreateProcess, CreateProcessOption, CreateProcessOptionArgs,
-        JoinProcess,
-    },
-    thread::JoinStatus,
-};
+use core::cell::RefCell;
 
+use alloc::{
+    borrow::Cow, boxed::Box, collections::BTreeMap, format, rc::Rc, string::String, vec::Vec,
+};
+use lilium_sys::sys::{handle::HandlePtr, io::IOHandle};
 use ministd::{
-    eprintln, exit,
-    helpers::SplitOnceOwned,
-    io::{self, Error},
-    println,
+    env, eprintln, exit,
+    fs::{File, OpenOptions},
+    io::{self, Error, PipeReader, PipeWriter, ReadEx},
+    print, println,
+    process::{self, Child, Command, ExitStatus, Stdio},
 };
 
+use crate::{editor::History, pattern};
+
 pub fn split_shell(x: &str) -> SplitShell {
     SplitShell(x)
 }
@@ -37,103 +29,536 @@ enum State {
 
 pub struct SplitShell<'a>(&'a str);
 
+/// A token produced by [`SplitShell`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Token<'a> {
+    Word(Word<'a>),
+    Op(Operator),
+    /// A quote or escape that is not closed before the end of the input.
+    Unterminated,
+}
+
+/// An unquoted control operator.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Operator {
+    /// `|`
+    Pipe,
+    /// `<`
+    RedirectIn,
+    /// `>`
+    RedirectOut,
+    /// `>>`
+    AppendOut,
+    /// `2>`
+    RedirectErr,
+    /// `2>>`
+    AppendErr,
+    /// `2>&1`
+    ErrToOut,
+    /// `;`
+    Semi,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// `&`
+    Background,
+    /// `;;`
+    DoubleSemi,
+    /// `(`
+    LParen,
+    /// `)`
+    RParen,
+    /// A line break, which separates commands like `;`
+    Newline,
+}
+
+impl Operator {
+    const ALL: [Self; 15] = [
+        Operator::ErrToOut,
+        Operator::AppendErr,
+        Operator::RedirectErr,
+        Operator::AppendOut,
+        Operator::RedirectOut,
+        Operator::RedirectIn,
+        Operator::DoubleSemi,
+        Operator::Semi,
+        Operator::And,
+        Operator::Or,
+        Operator::Background,
+        Operator::Pipe,
+        Operator::LParen,
+        Operator::RParen,
+        Operator::Newline,
+    ];
+
+    /// Recognizes the operator at the start of `s`, returning it and its length.
+    ///
+    /// Operators that begin with a file descriptor number (like `2>`) are only recognized at the start of a word.
+    fn parse(s: &str, word_start: bool) -> Option<(Self, usize)> {
+        // Longer operators come first in `ALL`, so that `>>` isn't read as `>`
+        Self::ALL
+            .into_iter()
+            .filter(|op| word_start || !op.as_str().starts_with('2'))
+            .find(|op| s.starts_with(op.as_str()))
+            .map(|op| (op, op.as_str().len()))
+    }
+
+    /// Whether the operator redirects one of the standard I/O handles of a command.
+    fn is_redirect(self) -> bool {
+        matches!(
+            self,
+            Operator::RedirectIn
+                | Operator::RedirectOut
+                | Operator::AppendOut
+                | Operator::RedirectErr
+                | Operator::AppendErr
+                | Operator::ErrToOut
+        )
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Operator::Pipe => "|",
+            Operator::RedirectIn => "<",
+            Operator::RedirectOut => ">",
+            Operator::AppendOut => ">>",
+            Operator::RedirectErr => "2>",
+            Operator::AppendErr => "2>>",
+            Operator::ErrToOut => "2>&1",
+            Operator::Semi => ";",
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Background => "&",
+            Operator::DoubleSemi => ";;",
+            Operator::LParen => "(",
+            Operator::RParen => ")",
+            Operator::Newline => "\n",
+        }
+    }
+}
+
+impl core::fmt::Display for Operator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Operator::Newline => f.write_str("newline"),
+            op => f.write_str(op.as_str()),
+        }
+    }
+}
+
+/// Whitespace other than a line break, which is a token of its own.
+fn is_blank(c: char) -> bool {
+    c.is_whitespace() && c != '\n'
+}
+
 impl<'a> Iterator for SplitShell<'a> {
-    type Item = Cow<'a, str>;
+    type Item = Token<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut state = State::Normal;
-
-        let s = self.0.trim();
+        let mut s = self.0.trim_start_matches(is_blank);
+        while s.starts_with('#') {
+            // A comment runs to the end of the line
+            s = s
+                .find('\n')
+                .map_or("", |n| &s[n..])
+                .trim_start_matches(is_blank);
+        }
         if s.is_empty() {
             self.0 = s;
             return None;
         }
 
-        let mut buf = String::new();
+        if let Some((op, len)) = Operator::parse(s, true) {
+            self.0 = &s[len..];
+            return Some(Token::Op(op));
+        }
 
-        for (n, c) in s.char_indices() {
-            match state {
-                State::Normal => match c {
-                    c if c.is_whitespace() => {
-                        self.0 = &s[n..];
+        let (word, len, terminated) = lex_word(s, false);
+        self.0 = &s[len..];
+        if terminated {
+            Some(Token::Word(word))
+        } else {
+            Some(Token::Unterminated)
+        }
+    }
+}
 
-                        if !buf.is_empty() {
-                            return Some(Cow::Owned(buf));
-                        } else {
-                            return Some(Cow::Borrowed(&s[..n]));
+/// A piece of a [`Word`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WordPart<'a> {
+    /// Unquoted text.
+    Literal(&'a str),
+    /// Text that was quoted or escaped.
+    Quoted(Cow<'a, str>),
+    /// `$NAME`, `${NAME}`, or `${NAME:-default}`, which is expanded when the command runs.
+    Param {
+        name: &'a str,
+        /// Used when the parameter is unset or empty.
+        default: Option<Word<'a>>,
+        /// Whether the expansion appeared inside double quotes.
+        quoted: bool,
+    },
+    /// `$(COMMAND)` or `` `COMMAND` ``, which is replaced by the output of the command when it runs.
+    Command {
+        src: Cow<'a, str>,
+        /// Whether the substitution appeared inside double quotes.
+        quoted: bool,
+    },
+}
+
+/// A word of a command, before expansion.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Word<'a> {
+    pub parts: Vec<WordPart<'a>>,
+}
+
+impl<'a> Word<'a> {
+    fn push_literal(&mut self, s: &'a str) {
+        if !s.is_empty() {
+            self.parts.push(WordPart::Literal(s));
+        }
+    }
+
+    /// If the word is plain unquoted text, returns that text.
+    pub fn as_literal(&self) -> Option<&'a str> {
+        match self.parts[..] {
+            [WordPart::Literal(s)] => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> core::fmt::Display for Word<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use core::fmt::Write;
+        for part in &self.parts {
+            match part {
+                WordPart::Literal(s) => f.write_str(s)?,
+                WordPart::Quoted(s) => {
+                    f.write_char('"')?;
+                    for c in s.chars() {
+                        if matches!(c, '"' | '\\' | '$') {
+                            f.write_char('\\')?;
                         }
+                        f.write_char(c)?;
                     }
-                    '\\' => {
-                        buf.push_str(&s[..n]);
-                        state = State::Escape;
-                    }
-                    '"' => {
-                        buf.push_str(&s[..n]);
-                        state = State::DQuote;
+                    f.write_char('"')?;
+                }
+                WordPart::Param {
+                    name,
+                    default: None,
+                    ..
+                } => f.write_fmt(format_args!("${{{name}}}"))?,
+                WordPart::Param {
+                    name,
+                    default: Some(default),
+                    ..
+                } => f.write_fmt(format_args!("${{{name}:-{default}}}"))?,
+                WordPart::Command { src, .. } => f.write_fmt(format_args!("$({src})"))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the command substitution at the start of `s`, returning the command inside it and the length of the substitution.
+///
+/// For `$(...)`, the command is split into tokens to find the `)` that closes it, so parentheses that are quoted
+/// or in nested substitutions are skipped. A `)` after a `case` pattern must be balanced by a `(` before it.
+/// Inside `` `...` ``, `\` only escapes `` ` ``, `$` and `\`.
+///
+/// Returns `None` if the substitution is not closed before the end of `s`.
+fn lex_command(s: &str) -> Option<(Cow<'_, str>, usize)> {
+    if let Some(inner) = s.strip_prefix("$(") {
+        let mut tokens = SplitShell(inner);
+        let mut depth = 0usize;
+        loop {
+            match tokens.next()? {
+                Token::Op(Operator::LParen) => depth += 1,
+                Token::Op(Operator::RParen) if depth == 0 => {
+                    let end = inner.len() - tokens.0.len();
+                    return Some((Cow::Borrowed(&inner[..end - 1]), 2 + end));
+                }
+                Token::Op(Operator::RParen) => depth -= 1,
+                Token::Unterminated => return None,
+                _ => {}
+            }
+        }
+    }
+
+    let mut src = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '`' => return Some((Cow::Owned(src), i + 1)),
+            '\\' => {
+                let (_, c) = chars.next()?;
+                if !matches!(c, '`' | '$' | '\\') {
+                    src.push('\\');
+                }
+                src.push(c);
+            }
+            c => src.push(c),
+        }
+    }
+    None
+}
+
+/// Reads the word at the start of `s`, returning it, its length, and whether its quotes and escapes are closed.
+///
+/// Inside `${NAME:-...}` (when `nested` is set), whitespace and operators are part of the word,
+/// which ends at the first unquoted `}`.
+fn lex_word<'a>(s: &'a str, nested: bool) -> (Word<'a>, usize, bool) {
+    let mut state = State::Normal;
+    let mut word = Word::default();
+    // Quoted text is collected in `buf`. `start` is the beginning of the unquoted text that has not been pushed yet
+    let mut buf = String::new();
+    let mut start = 0;
+    let mut n = 0;
+
+    while let Some(c) = s[n..].chars().next() {
+        let next = n + c.len_utf8();
+        match state {
+            State::Normal => match c {
+                '}' if nested => break,
+                c if !nested
+                    && (c.is_whitespace() || Operator::parse(&s[n..], false).is_some()) =>
+                {
+                    break;
+                }
+                '\\' => {
+                    word.push_literal(&s[start..n]);
+                    state = State::Escape;
+                }
+                '"' => {
+                    word.push_literal(&s[start..n]);
+                    state = State::DQuote;
+                }
+                '\'' => {
+                    word.push_literal(&s[start..n]);
+                    state = State::SQuote;
+                }
+                c if c == '`' || s[n..].starts_with("$(") => {
+                    word.push_literal(&s[start..n]);
+                    let Some((src, len)) = lex_command(&s[n..]) else {
+                        return (word, s.len(), false);
+                    };
+                    word.parts.push(WordPart::Command { src, quoted: false });
+                    n += len;
+                    start = n;
+                    continue;
+                }
+                '$' => {
+                    if let Some((part, len)) = lex_param(&s[n..], false) {
+                        word.push_literal(&s[start..n]);
+                        word.parts.push(part);
+                        n += len;
+                        start = n;
+                        continue;
                     }
-                    '\'' => {
-                        buf.push_str(&s[..n]);
-                        state = State::SQuote;
+                }
+                _ => {}
+            },
+            // An escaped line break continues the line
+            State::Escape if c == '\n' => {
+                start = next;
+                state = State::Normal;
+            }
+            State::Escape => {
+                buf.push(c);
+                word.parts
+                    .push(WordPart::Quoted(Cow::Owned(core::mem::take(&mut buf))));
+                start = next;
+                state = State::Normal;
+            }
+            State::EscapeDQuote if c == '\n' => state = State::DQuote,
+            State::EscapeDQuote => {
+                buf.push(c);
+                state = State::DQuote;
+            }
+            State::EscapeSQuote => {
+                buf.push(c);
+                state = State::SQuote;
+            }
+            State::DQuote => match c {
+                '"' => {
+                    word.parts
+                        .push(WordPart::Quoted(Cow::Owned(core::mem::take(&mut buf))));
+                    start = next;
+                    state = State::Normal;
+                }
+                '\\' => state = State::EscapeDQuote,
+                c if c == '`' || s[n..].starts_with("$(") => {
+                    if !buf.is_empty() {
+                        word.parts
+                            .push(WordPart::Quoted(Cow::Owned(core::mem::take(&mut buf))));
                     }
-                    ';' => {
-                        if n == 0 {
-                            self.0 = &s[1..];
-
-                            if !buf.is_empty() {
-                                return Some(Cow::Owned(buf));
-                            } else {
-                                return Some(Cow::Borrowed(&s[..1]));
-                            }
-                        } else {
-                            self.0 = &s[n..];
-                            if !buf.is_empty() {
-                                return Some(Cow::Owned(buf));
-                            } else {
-                                return Some(Cow::Borrowed(&s[..n]));
-                            }
+                    let Some((src, len)) = lex_command(&s[n..]) else {
+                        return (word, s.len(), false);
+                    };
+                    word.parts.push(WordPart::Command { src, quoted: true });
+                    n += len;
+                    continue;
+                }
+                '$' => match lex_param(&s[n..], true) {
+                    Some((part, len)) => {
+                        if !buf.is_empty() {
+                            word.parts
+                                .push(WordPart::Quoted(Cow::Owned(core::mem::take(&mut buf))));
                         }
+                        word.parts.push(part);
+                        n += len;
+                        continue;
                     }
-                    _ => continue,
+                    None => buf.push(c),
                 },
-                State::Escape => {
-                    buf.push(c);
+                _ => buf.push(c),
+            },
+            State::SQuote => match c {
+                '\'' => {
+                    word.parts
+                        .push(WordPart::Quoted(Cow::Owned(core::mem::take(&mut buf))));
+                    start = next;
                     state = State::Normal;
                 }
-                State::EscapeDQuote => {
-                    buf.push(c);
-                    state = State::DQuote;
-                }
-                State::EscapeSQuote => {
-                    buf.push(c);
-                    state = State::SQuote;
+                '\\' => state = State::EscapeSQuote,
+                _ => buf.push(c),
+            },
+        }
+        n = next;
+    }
+
+    match state {
+        State::Normal => {
+            word.push_literal(&s[start..n]);
+            (word, n, true)
+        }
+        _ => (word, n, false),
+    }
+}
+
+/// Reads the parameter expansion at the start of `s`, which begins with `$`.
+///
+/// Returns `None` if the `$` does not start an expansion, in which case it's an ordinary character.
+fn lex_param<'a>(s: &'a str, quoted: bool) -> Option<(WordPart<'a>, usize)> {
+    let rest = &s[1..];
+    let name_len = |s: &str| match s.as_bytes().first()? {
+        b'?' | b'$' | b'#' | b'!' | b'0'..=b'9' => Some(1),
+        b'a'..=b'z' | b'A'..=b'Z' | b'_' => Some(
+            s.bytes()
+                .position(|b| !(b.is_ascii_alphanumeric() || b == b'_'))
+                .unwrap_or(s.len()),
+        ),
+        _ => None,
+    };
+
+    if let Some(braced) = rest.strip_prefix('{') {
+        let len = name_len(braced)?;
+        let (name, after) = braced.split_at(len);
+        if after.starts_with('}') {
+            let part = WordPart::Param {
+                name,
+                default: None,
+                quoted,
+            };
+            Some((part, 3 + len))
+        } else if let Some(text) = after.strip_prefix(":-") {
+            let (mut default, default_len, terminated) = lex_word(text, true);
+            if !terminated || !text[default_len..].starts_with('}') {
+                return None;
+            }
+            if quoted {
+                for part in &mut default.parts {
+                    if let WordPart::Literal(s) = *part {
+                        *part = WordPart::Quoted(Cow::Borrowed(s));
+                    }
                 }
-                State::DQuote => match c {
-                    '"' => state = State::Normal,
-                    '\\' => state = State::EscapeDQuote,
-                    _ => buf.push(c),
-                },
-                State::SQuote => match c {
-                    '\'' => state = State::Normal,
-                    '\\' => state = State::EscapeSQuote,
-                    _ => buf.push(c),
-                },
             }
+            let part = WordPart::Param {
+                name,
+                default: Some(default),
+                quoted,
+            };
+            Some((part, 5 + len + default_len))
+        } else {
+            None
         }
-        self.0 = &self.0[self.0.len()..];
-
-        Some(Cow::Borrowed(s))
+    } else {
+        let len = name_len(rest)?;
+        let part = WordPart::Param {
+            name: &rest[..len],
+            default: None,
+            quoted,
+        };
+        Some((part, 1 + len))
     }
 }
 
 pub struct EnvVar<'a> {
-    pub key: Cow<'a, str>,
-    pub val: Cow<'a, str>,
+    pub key: &'a str,
+    pub val: Word<'a>,
+}
+
+/// A redirection of one of the standard I/O handles of a command.
+pub enum Redirect<'a> {
+    /// `< path`
+    Stdin(Word<'a>),
+    /// `> path`, or `>> path` if `append` is set
+    Stdout { path: Word<'a>, append: bool },
+    /// `2> path`, or `2>> path` if `append` is set
+    Stderr { path: Word<'a>, append: bool },
+    /// `2>&1`
+    StderrToStdout,
+}
+
+impl<'a> core::fmt::Display for Redirect<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (op, path) = match self {
+            Redirect::Stdin(path) => (Operator::RedirectIn, path),
+            Redirect::Stdout { path, append } => (
+                if *append {
+                    Operator::AppendOut
+                } else {
+                    Operator::RedirectOut
+                },
+                path,
+            ),
+            Redirect::Stderr { path, append } => (
+                if *append {
+                    Operator::AppendErr
+                } else {
+                    Operator::RedirectErr
+                },
+                path,
+            ),
+            Redirect::StderrToStdout => return f.write_str(Operator::ErrToOut.as_str()),
+        };
+        f.write_fmt(format_args!("{op} {path}"))
+    }
 }
 
 pub struct ShellLine<'a> {
     pub env: Vec<EnvVar<'a>>,
-    pub command: Option<Cow<'a, str>>,
-    pub args: Vec<Cow<'a, str>>,
+    pub command: Option<Word<'a>>,
+    pub args: Vec<Word<'a>>,
+    /// Applied in order, so `> path 2>&1` sends both stdout and stderr to `path`.
+    pub redirects: Vec<Redirect<'a>>,
+}
+
+impl<'a> ShellLine<'a> {
+    const fn new() -> Self {
+        Self {
+            env: Vec::new(),
+            command: None,
+            args: Vec::new(),
+            redirects: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.env.is_empty() && self.command.is_none() && self.redirects.is_empty()
+    }
 }
 
 impl<'a> core::fmt::Display for ShellLine<'a> {
@@ -142,147 +567,2060 @@ impl<'a> core::fmt::Display for ShellLine<'a> {
         for v in &self.env {
             f.write_str(sep)?;
             sep = " ";
-            f.write_str(&v.key)?;
-            f.write_str("=")?;
-            f.write_str(&v.val)?;
+            f.write_fmt(format_args!("{}={}", v.key, v.val))?;
         }
 
         if let Some(cmd) = &self.command {
             f.write_str(sep)?;
-            f.write_str(cmd)?;
+            sep = " ";
+            core::fmt::Display::fmt(cmd, f)?;
         }
 
         for a in &self.args {
             f.write_str(" ")?;
-            f.write_str(a)?;
+            core::fmt::Display::fmt(a, f)?;
+        }
+
+        for r in &self.redirects {
+            f.write_str(sep)?;
+            sep = " ";
+            core::fmt::Display::fmt(r, f)?;
         }
         Ok(())
     }
 }
 
-pub fn parse_shell<'a, I: Iterator<Item = Cow<'a, str>>>(mut iter: I) -> ShellLine<'a> {
-    let mut line = ShellLine {
-        env: Vec::new(),
-        command: None,
-        args: Vec::new(),
-    };
-    for c in &mut iter {
-        match c.split_once_owned("=") {
-            Ok((k, v)) => line.env.push(EnvVar { key: k, val: v }),
-            Err(e) => {
-                line.command = Some(e);
-                break;
-            }
+/// A command in a [`Pipeline`].
+pub enum Stage<'a> {
+    Simple(ShellLine<'a>),
+    Compound(Compound<'a>),
+    Function(Function<'a>),
+}
+
+impl<'a> core::fmt::Display for Stage<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Stage::Simple(line) => core::fmt::Display::fmt(line, f),
+            Stage::Compound(compound) => core::fmt::Display::fmt(compound, f),
+            Stage::Function(function) => core::fmt::Display::fmt(function, f),
+        }
+    }
+}
+
+/// A sequence of commands, each with its stdout connected to the stdin of the next.
+pub struct Pipeline<'a> {
+    pub commands: Vec<Stage<'a>>,
+}
+
+impl<'a> core::fmt::Display for Pipeline<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut sep = "";
+        for cmd in &self.commands {
+            f.write_str(sep)?;
+            sep = " | ";
+            core::fmt::Display::fmt(cmd, f)?;
         }
+        Ok(())
     }
+}
 
-    line.args.extend(iter);
+/// One branch of a `case` command: `PATTERN | PATTERN) LIST ;;`
+pub struct CaseArm<'a> {
+    pub patterns: Vec<Word<'a>>,
+    pub body: List<'a>,
+}
 
-    line
+/// The definition of a function: `NAME() COMPOUND`
+pub struct Function<'a> {
+    pub name: &'a str,
+    pub body: Box<Compound<'a>>,
+    /// The source text of `body`, which is parsed again each time the function is called.
+    pub src: &'a str,
 }
 
-#[thread_local]
-static PATH: LazyCell<Vec<(&'static str, HandlePtr<FileHandle>)>> = LazyCell::new(|| {
-    let v = ministd::start::var("PATH")
-        .into_iter()
-        .flat_map(|v| v.split(':'))
-        .filter_map(|v| {
-            let mut hdl = HandlePtr::null();
-            lilium_sys::result::Error::from_code(unsafe {
-                OpenFile(
-                    &mut hdl,
-                    HandlePtr::null(),
-                    KStrCPtr::from_str(v),
-                    &FileOpenOptions {
-                        stream_override: KStrCPtr::empty(),
-                        access_mode: ACCESS_READ,
-                        op_mode: OP_DIRECTORY_ACCESS,
-                        create_acl: HandlePtr::null(),
-                        blocking_mode: MODE_BLOCKING,
-                        extended_options: KCSlice::empty(),
-                    },
-                )
-            })
-            .ok()
-            .map(|_| (v, hdl))
-        })
-        .collect();
-    v
-});
+impl<'a> core::fmt::Display for Function<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_fmt(format_args!("{}() {}", self.name, self.body))
+    }
+}
 
-pub fn exec_line(line: &ShellLine) -> io::Result<Option<JoinStatus>> {
-    match line.command.as_deref() {
-        Some(c @ ("return" | "exit" | "logout")) => {
-            println!("exit command: {c}");
-            let status = if let Some(status) = line.args.get(0).map(|v| &**v) {
-                let val = status
-                    .parse()
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
-                val
-            } else {
-                0
-            };
-            exit(status)
-        }
-        Some(n) => {
-            let mut hdl = HandlePtr::null();
-            let args = line
-                .command
-                .iter()
-                .chain(line.args.iter())
-                .map(Deref::deref)
-                .map(KStrCPtr::from_str)
-                .collect::<Vec<_>>();
-
-            let opts = [CreateProcessOption {
-                args: CreateProcessOptionArgs {
-                    header: ExtendedOptionHead {
-                        ty: CREATE_PROCESS_OPTION_ARGS,
-                        ..ExtendedOptionHead::ZERO
-                    },
-                    arguments: KCSlice::from_slice(&args),
-                },
-            }];
-            if !n.contains('/') {
-                'a: {
-                    let mut res = lilium_sys::sys::error::DOES_NOT_EXIST;
-                    for (path, path_ent) in PATH.iter().copied() {
-                        res = unsafe {
-                            CreateProcess(
-                                &mut hdl,
-                                path_ent,
-                                &KStrCPtr::from_str(n),
-                                &KCSlice::from_slice(&opts),
-                            )
-                        };
-                        if res >= 0 {
-                            break 'a;
-                        }
+/// A command that is made up of other commands.
+pub enum Compound<'a> {
+    /// `{ LIST; }`
+    Group(List<'a>),
+    /// `if LIST; then LIST; [elif LIST; then LIST;]... [else LIST;] fi`
+    If {
+        branches: Vec<(List<'a>, List<'a>)>,
+        otherwise: Option<List<'a>>,
+    },
+    /// `while LIST; do LIST; done`, or `until LIST; do LIST; done` if `until` is set
+    While {
+        cond: List<'a>,
+        body: List<'a>,
+        until: bool,
+    },
+    /// `for NAME [in WORD...]; do LIST; done`
+    ///
+    /// Without `in`, loops over the positional parameters.
+    For {
+        name: &'a str,
+        words: Option<Vec<Word<'a>>>,
+        body: List<'a>,
+    },
+    /// `case WORD in [PATTERN) LIST ;;]... esac`
+    Case {
+        word: Word<'a>,
+        arms: Vec<CaseArm<'a>>,
+    },
+}
+
+impl<'a> core::fmt::Display for Compound<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Compound::Group(list) => f.write_fmt(format_args!("{{ {list}; }}")),
+            Compound::If {
+                branches,
+                otherwise,
+            } => {
+                let mut kw = "if";
+                for (cond, body) in branches {
+                    f.write_fmt(format_args!("{kw} {cond}; then {body}; "))?;
+                    kw = "elif";
+                }
+                if let Some(otherwise) = otherwise {
+                    f.write_fmt(format_args!("else {otherwise}; "))?;
+                }
+                f.write_str("fi")
+            }
+            Compound::While { cond, body, until } => {
+                let kw = if *until { "until" } else { "while" };
+                f.write_fmt(format_args!("{kw} {cond}; do {body}; done"))
+            }
+            Compound::For { name, words, body } => {
+                f.write_fmt(format_args!("for {name}"))?;
+                if let Some(words) = words {
+                    f.write_str(" in")?;
+                    for word in words {
+                        f.write_fmt(format_args!(" {word}"))?;
                     }
-                    return Err(io::Error::from_raw_os_error(res));
                 }
-            } else {
-                let res = unsafe {
-                    CreateProcess(
-                        &mut hdl,
-                        HandlePtr::null(),
-                        &KStrCPtr::from_str(n),
-                        &KCSlice::from_slice(&opts),
-                    )
-                };
-                if res < 0 {
-                    return Err(io::Error::from_raw_os_error(res));
+                f.write_fmt(format_args!("; do {body}; done"))
+            }
+            Compound::Case { word, arms } => {
+                f.write_fmt(format_args!("case {word} in "))?;
+                for arm in arms {
+                    let mut sep = "";
+                    for pattern in &arm.patterns {
+                        f.write_fmt(format_args!("{sep}{pattern}"))?;
+                        sep = " | ";
+                    }
+                    f.write_fmt(format_args!(") {};; ", arm.body))?;
                 }
+                f.write_str("esac")
             }
-            let mut status = bytemuck::zeroed();
-            let res = unsafe { JoinProcess(hdl, &mut status) };
+        }
+    }
+}
 
-            if res < 0 {
-                Err(io::Error::from_raw_os_error(res))
-            } else {
-                Ok(Some(status))
+/// How a pipeline in an [`AndOr`] chain is joined to the one before it.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Connector {
+    /// `&&`: runs the pipeline only if the previous one succeeded.
+    And,
+    /// `||`: runs the pipeline only if the previous one failed.
+    Or,
+}
+
+impl core::fmt::Display for Connector {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Connector::And => "&&",
+            Connector::Or => "||",
+        })
+    }
+}
+
+/// A chain of pipelines joined by `&&` and `||`, which are evaluated left to right.
+pub struct AndOr<'a> {
+    pub first: Pipeline<'a>,
+    pub rest: Vec<(Connector, Pipeline<'a>)>,
+    /// Whether the chain ends in `&`, so that the shell doesn't wait for it.
+    pub background: bool,
+}
+
+impl<'a> core::fmt::Display for AndOr<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Display::fmt(&self.first, f)?;
+        for (conn, pipeline) in &self.rest {
+            f.write_fmt(format_args!(" {conn} {pipeline}"))?;
+        }
+        if self.background {
+            f.write_str(" &")?;
+        }
+        Ok(())
+    }
+}
+
+/// A sequence of [`AndOr`] chains separated by `;`, `&` or line breaks, which are run one after another.
+pub struct List<'a> {
+    pub items: Vec<AndOr<'a>>,
+}
+
+impl<'a> List<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl<'a> core::fmt::Display for List<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut sep = "";
+        for item in &self.items {
+            f.write_str(sep)?;
+            // `&` already separates the chain from the next one
+            sep = if item.background { " " } else { "; " };
+            core::fmt::Display::fmt(item, f)?;
+        }
+        Ok(())
+    }
+}
+
+/// An error in the syntax of the input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The input ends in the middle of a command, which may continue on the following lines.
+    Incomplete,
+    Syntax(Cow<'static, str>),
+}
+
+impl core::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("syntax error: ")?;
+        match self {
+            ParseError::Incomplete => f.write_str("unexpected end of input"),
+            ParseError::Syntax(msg) => f.write_str(msg),
+        }
+    }
+}
+
+fn unexpected(tok: &Token) -> ParseError {
+    match tok {
+        Token::Word(word) => ParseError::Syntax(Cow::Owned(format!("unexpected `{word}`"))),
+        Token::Op(op) => ParseError::Syntax(Cow::Owned(format!("unexpected `{op}`"))),
+        Token::Unterminated => ParseError::Incomplete,
+    }
+}
+
+/// Whether `name` can be the name of a variable.
+fn is_valid_name(name: &str) -> bool {
+    name.bytes()
+        .next()
+        .is_some_and(|b| b.is_ascii_alphabetic() || b == b'_')
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// Splits a word of the form `NAME=value` into an [`EnvVar`], or returns it unchanged.
+fn split_assignment<'a>(mut word: Word<'a>) -> Result<EnvVar<'a>, Word<'a>> {
+    let Some(&WordPart::Literal(first)) = word.parts.first() else {
+        return Err(word);
+    };
+    let Some((key, val)) = first.split_once('=') else {
+        return Err(word);
+    };
+    if !is_valid_name(key) {
+        return Err(word);
+    }
+
+    word.parts.remove(0);
+    if !val.is_empty() {
+        word.parts.insert(0, WordPart::Literal(val));
+    }
+    Ok(EnvVar { key, val: word })
+}
+
+pub fn parse_shell<'a>(tokens: SplitShell<'a>) -> Result<List<'a>, ParseError> {
+    let mut parser = Parser {
+        lexer: tokens,
+        peeked: None,
+    };
+    let list = parser.list(&[])?;
+    match parser.peek() {
+        Some(tok) => Err(unexpected(tok)),
+        None => Ok(list),
+    }
+}
+
+/// A recursive descent parser over the tokens of [`SplitShell`].
+struct Parser<'a> {
+    lexer: SplitShell<'a>,
+    /// The next token, if it has been read, and the input that it was read from.
+    peeked: Option<(&'a str, Option<Token<'a>>)>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<&Token<'a>> {
+        let lexer = &mut self.lexer;
+        self.peeked
+            .get_or_insert_with(|| (lexer.0, lexer.next()))
+            .1
+            .as_ref()
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        match self.peeked.take() {
+            Some((_, tok)) => tok,
+            None => self.lexer.next(),
+        }
+    }
+
+    fn next_if(&mut self, f: impl FnOnce(&Token<'a>) -> bool) -> Option<Token<'a>> {
+        if self.peek().is_some_and(f) {
+            self.next()
+        } else {
+            None
+        }
+    }
+
+    fn next_if_eq(&mut self, tok: &Token<'a>) -> Option<Token<'a>> {
+        self.next_if(|next| next == tok)
+    }
+
+    /// The input that has not been parsed yet.
+    fn rest(&self) -> &'a str {
+        self.peeked.as_ref().map_or(self.lexer.0, |(rest, _)| rest)
+    }
+
+    /// Whether the next token is one of `stops`, which are reserved words or operators.
+    fn at_stop(&mut self, stops: &[&str]) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) => word.as_literal().is_some_and(|w| stops.contains(&w)),
+            Some(Token::Op(op)) => stops.contains(&op.as_str()),
+            _ => false,
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.next_if_eq(&Token::Op(Operator::Newline)).is_some() {}
+    }
+
+    /// Reads the reserved word `kw`, which must come next.
+    fn expect(&mut self, kw: &str) -> Result<(), ParseError> {
+        match self.next() {
+            Some(Token::Word(word)) if word.as_literal() == Some(kw) => Ok(()),
+            Some(Token::Word(word)) => Err(ParseError::Syntax(Cow::Owned(format!(
+                "expected `{kw}`, found `{word}`"
+            )))),
+            Some(tok) => Err(unexpected(&tok)),
+            None => Err(ParseError::Incomplete),
+        }
+    }
+
+    /// Reads the next word, which must come next.
+    fn word(&mut self) -> Result<Word<'a>, ParseError> {
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word),
+            Some(tok) => Err(unexpected(&tok)),
+            None => Err(ParseError::Incomplete),
+        }
+    }
+
+    /// Reads commands up to the first of `stops`, which is left for the caller to read.
+    ///
+    /// The input may only end first if `stops` is empty.
+    fn list(&mut self, stops: &[&str]) -> Result<List<'a>, ParseError> {
+        let mut list = List { items: Vec::new() };
+        loop {
+            self.skip_newlines();
+            if self.at_stop(stops) {
+                return Ok(list);
+            }
+            if self.peek().is_none() {
+                return if stops.is_empty() {
+                    Ok(list)
+                } else {
+                    Err(ParseError::Incomplete)
+                };
+            }
+
+            let mut chain = self.and_or()?;
+            // `&` also separates the chain from the next one
+            chain.background = self.next_if_eq(&Token::Op(Operator::Background)).is_some();
+            let separated = chain.background
+                || self
+                    .next_if(|tok| matches!(tok, Token::Op(Operator::Semi | Operator::Newline)))
+                    .is_some();
+            list.items.push(chain);
+
+            if !separated
+                && !self.at_stop(stops)
+                && let Some(tok) = self.peek()
+            {
+                return Err(unexpected(tok));
             }
         }
-        None => Ok(None),
     }
+
+    fn and_or(&mut self) -> Result<AndOr<'a>, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let conn = match self.peek() {
+                Some(Token::Op(Operator::And)) => Connector::And,
+                Some(Token::Op(Operator::Or)) => Connector::Or,
+                _ => {
+                    return Ok(AndOr {
+                        first,
+                        rest,
+                        background: false,
+                    });
+                }
+            };
+            self.next();
+            self.skip_newlines();
+            rest.push((conn, self.pipeline()?));
+        }
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline<'a>, ParseError> {
+        let mut commands = Vec::new();
+        commands.push(self.command()?);
+        while self.next_if_eq(&Token::Op(Operator::Pipe)).is_some() {
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline { commands })
+    }
+
+    fn command(&mut self) -> Result<Stage<'a>, ParseError> {
+        let kw = match self.peek() {
+            Some(Token::Word(word)) => word.as_literal(),
+            _ => None,
+        };
+        let compound = match kw {
+            Some("{") => self.group()?,
+            Some("if") => self.if_command()?,
+            Some(kw @ ("while" | "until")) => self.while_command(kw == "until")?,
+            Some("for") => self.for_command()?,
+            Some("case") => self.case_command()?,
+            Some(kw @ ("}" | "then" | "elif" | "else" | "fi" | "do" | "done" | "esac")) => {
+                return Err(ParseError::Syntax(Cow::Owned(format!("unexpected `{kw}`"))));
+            }
+            _ => {
+                let line = self.simple_command()?;
+                if self.peek() == Some(&Token::Op(Operator::LParen)) {
+                    return self.function(line).map(Stage::Function);
+                }
+                return Ok(Stage::Simple(line));
+            }
+        };
+        Ok(Stage::Compound(compound))
+    }
+
+    /// Reads the rest of a function definition, after its name has been read as `line`.
+    fn function(&mut self, line: ShellLine<'a>) -> Result<Function<'a>, ParseError> {
+        let name = match (
+            &line.env[..],
+            &line.command,
+            &line.args[..],
+            &line.redirects[..],
+        ) {
+            ([], Some(word), [], []) => word.as_literal().filter(|name| is_valid_name(name)),
+            _ => None,
+        };
+        let Some(name) = name else {
+            return Err(unexpected(&Token::Op(Operator::LParen)));
+        };
+
+        self.next();
+        match self.next() {
+            Some(Token::Op(Operator::RParen)) => {}
+            Some(tok) => return Err(unexpected(&tok)),
+            None => return Err(ParseError::Incomplete),
+        }
+        self.skip_newlines();
+
+        let start = self.rest();
+        let Stage::Compound(body) = self.command()? else {
+            return Err(ParseError::Syntax(Cow::Owned(format!(
+                "the body of `{name}` must be a compound command"
+            ))));
+        };
+        let end = self.rest();
+        Ok(Function {
+            name,
+            body: Box::new(body),
+            src: start[..start.len() - end.len()].trim(),
+        })
+    }
+
+    fn group(&mut self) -> Result<Compound<'a>, ParseError> {
+        self.expect("{")?;
+        let list = self.list(&["}"])?;
+        self.expect("}")?;
+        Ok(Compound::Group(list))
+    }
+
+    fn simple_command(&mut self) -> Result<ShellLine<'a>, ParseError> {
+        let mut line = ShellLine::new();
+
+        while let Some(tok) = self.next_if(|tok| match tok {
+            Token::Word(_) => true,
+            Token::Op(op) => op.is_redirect(),
+            Token::Unterminated => false,
+        }) {
+            match tok {
+                Token::Word(word) if line.command.is_some() => line.args.push(word),
+                Token::Word(word) => match split_assignment(word) {
+                    Ok(var) => line.env.push(var),
+                    Err(word) => line.command = Some(word),
+                },
+                Token::Op(Operator::ErrToOut) => line.redirects.push(Redirect::StderrToStdout),
+                Token::Op(op) => {
+                    let path = match self.next() {
+                        Some(Token::Word(path)) => path,
+                        None | Some(Token::Unterminated) => return Err(ParseError::Incomplete),
+                        Some(_) => {
+                            return Err(ParseError::Syntax(Cow::Owned(format!(
+                                "expected a file name after `{op}`"
+                            ))));
+                        }
+                    };
+                    line.redirects.push(match op {
+                        Operator::RedirectIn => Redirect::Stdin(path),
+                        Operator::RedirectOut | Operator::AppendOut => Redirect::Stdout {
+                            path,
+                            append: op == Operator::AppendOut,
+                        },
+                        _ => Redirect::Stderr {
+                            path,
+                            append: op == Operator::AppendErr,
+                        },
+                    });
+                }
+                Token::Unterminated => unreachable!(),
+            }
+        }
+
+        if line.is_empty() {
+            return Err(match self.peek() {
+                Some(tok) => unexpected(tok),
+                None => ParseError::Incomplete,
+            });
+        }
+        Ok(line)
+    }
+
+    fn if_command(&mut self) -> Result<Compound<'a>, ParseError> {
+        self.expect("if")?;
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            let cond = self.list(&["then"])?;
+            self.expect("then")?;
+            let body = self.list(&["elif", "else", "fi"])?;
+            branches.push((cond, body));
+
+            // `list` stopped at one of the reserved words
+            let kw = self.word()?;
+            match kw.as_literal() {
+                Some("elif") => {}
+                Some("else") => {
+                    otherwise = Some(self.list(&["fi"])?);
+                    self.expect("fi")?;
+                    break;
+                }
+                _ => break,
+            }
+        }
+        Ok(Compound::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn while_command(&mut self, until: bool) -> Result<Compound<'a>, ParseError> {
+        self.expect(if until { "until" } else { "while" })?;
+        let cond = self.list(&["do"])?;
+        self.expect("do")?;
+        let body = self.list(&["done"])?;
+        self.expect("done")?;
+        Ok(Compound::While { cond, body, until })
+    }
+
+    fn for_command(&mut self) -> Result<Compound<'a>, ParseError> {
+        self.expect("for")?;
+        let word = self.word()?;
+        let name = match word.as_literal() {
+            Some(name) if is_valid_name(name) => name,
+            _ => {
+                return Err(ParseError::Syntax(Cow::Owned(format!(
+                    "`{word}` is not a valid name"
+                ))));
+            }
+        };
+
+        self.skip_newlines();
+        let mut words = None;
+        if self.at_stop(&["in"]) {
+            self.next();
+            let mut list = Vec::new();
+            while let Some(Token::Word(word)) = self.next_if(|tok| matches!(tok, Token::Word(_))) {
+                list.push(word);
+            }
+            words = Some(list);
+        }
+        self.next_if_eq(&Token::Op(Operator::Semi));
+        self.skip_newlines();
+
+        self.expect("do")?;
+        let body = self.list(&["done"])?;
+        self.expect("done")?;
+        Ok(Compound::For { name, words, body })
+    }
+
+    fn case_command(&mut self) -> Result<Compound<'a>, ParseError> {
+        self.expect("case")?;
+        let word = self.word()?;
+        self.skip_newlines();
+        self.expect("in")?;
+
+        let mut arms = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.at_stop(&["esac"]) {
+                self.next();
+                break;
+            }
+
+            self.next_if_eq(&Token::Op(Operator::LParen));
+            let mut patterns = Vec::new();
+            loop {
+                patterns.push(self.word()?);
+                match self.next() {
+                    Some(Token::Op(Operator::Pipe)) => {}
+                    Some(Token::Op(Operator::RParen)) => break,
+                    Some(tok) => return Err(unexpected(&tok)),
+                    None => return Err(ParseError::Incomplete),
+                }
+            }
+
+            let body = self.list(&[";;", "esac"])?;
+            arms.push(CaseArm { patterns, body });
+            // The `;;` after the last arm is optional
+            if self.next_if_eq(&Token::Op(Operator::DoubleSemi)).is_none() {
+                self.expect("esac")?;
+                break;
+            }
+        }
+        Ok(Compound::Case { word, arms })
+    }
+}
+
+/// The directories in `PATH`, and the value of `PATH` they were opened from.
+struct PathCache {
+    path: Option<String>,
+    dirs: Vec<(String, File)>,
+}
+
+#[thread_local]
+static PATH: RefCell<PathCache> = RefCell::new(PathCache {
+    path: None,
+    dirs: Vec::new(),
+});
+
+/// The standard I/O handles of a command after its redirections have been applied.
+struct Redirected {
+    stdin: HandlePtr<IOHandle>,
+    stdout: HandlePtr<IOHandle>,
+    stderr: HandlePtr<IOHandle>,
+    /// Keeps the files opened for the redirections open until the command is spawned.
+    _files: Vec<File>,
+}
+
+impl Redirected {
+    /// Opens the targets of the redirections of `line`, in order.
+    ///
+    /// `stdin` and `stdout` are the handles the command uses when they aren't redirected.
+    /// Failures are reported with the path of the target, and return `None`.
+    fn open(
+        shell: &mut Shell,
+        line: &ShellLine,
+        stdin: HandlePtr<IOHandle>,
+        stdout: HandlePtr<IOHandle>,
+    ) -> Option<Self> {
+        let mut this = Self {
+            stdin,
+            stdout,
+            stderr: io::stderr().as_raw_handle(),
+            _files: Vec::new(),
+        };
+
+        for redirect in &line.redirects {
+            let mut opts = OpenOptions::new();
+            let (path, slot) = match redirect {
+                Redirect::Stdin(path) => {
+                    opts.read(true);
+                    (path, &mut this.stdin)
+                }
+                Redirect::Stdout { path, append } => {
+                    opts.write(true)
+                        .create(true)
+                        .append(*append)
+                        .truncate(!*append);
+                    (path, &mut this.stdout)
+                }
+                Redirect::Stderr { path, append } => {
+                    opts.write(true)
+                        .create(true)
+                        .append(*append)
+                        .truncate(!*append);
+                    (path, &mut this.stderr)
+                }
+                Redirect::StderrToStdout => {
+                    this.stderr = this.stdout;
+                    continue;
+                }
+            };
+
+            let path = shell.expand_word(path);
+            match opts.open(&path) {
+                Ok(file) => {
+                    *slot = file.as_raw_handle().cast();
+                    this._files.push(file);
+                }
+                Err(e) => {
+                    eprintln!("Error opening {path}: {e}");
+                    return None;
+                }
+            }
+        }
+
+        Some(this)
+    }
+
+    fn apply(&self, cmd: &mut Command) {
+        // SAFETY: The handles are either the standard handles of the shell, or are kept open by `self`
+        // or by the caller, which keeps them open until `cmd` is spawned
+        unsafe {
+            cmd.stdin(Stdio::from_raw_handle(self.stdin))
+                .stdout(Stdio::from_raw_handle(self.stdout))
+                .stderr(Stdio::from_raw_handle(self.stderr));
+        }
+    }
+
+    /// Makes the handles the standard handles of the shell itself, until the returned guard is dropped.
+    ///
+    /// Builtins and functions run in the shell rather than being spawned, so this is how their redirections apply.
+    fn apply_to_shell(self) -> ShellStdio {
+        // SAFETY: The handles are kept open by the guard, which puts the previous ones back before closing them
+        let prev = unsafe {
+            [
+                io::set_stdin(self.stdin),
+                io::set_stdout(self.stdout),
+                io::set_stderr(self.stderr),
+            ]
+        };
+        ShellStdio {
+            prev,
+            _redirected: self,
+        }
+    }
+}
+
+/// Puts back the standard handles of the shell that were replaced by [`Redirected::apply_to_shell`] when dropped.
+struct ShellStdio {
+    /// stdin, stdout and stderr, in that order.
+    prev: [HandlePtr<IOHandle>; 3],
+    _redirected: Redirected,
+}
+
+impl Drop for ShellStdio {
+    fn drop(&mut self) {
+        // SAFETY: These were the handles of the shell before, which are still open
+        unsafe {
+            io::set_stdin(self.prev[0]);
+            io::set_stdout(self.prev[1]);
+            io::set_stderr(self.prev[2]);
+        }
+    }
+}
+
+/// The status of a command that could not be spawned.
+const STATUS_NOT_SPAWNED: i32 = 127;
+
+/// The commands that [`Shell::exec_line`] runs itself, rather than spawning them.
+pub const BUILTINS: &[&str] = &[
+    ".", "cd", "exit", "export", "fg", "history", "jobs", "logout", "pwd", "return", "set",
+    "source", "unset", "wait",
+];
+
+/// The status the shell reports for a child that finished with `status`.
+///
+/// Children that did not exit normally are reported as `-1`, which is never a success.
+fn status_code(status: ExitStatus) -> i32 {
+    status.code().unwrap_or(-1)
+}
+
+/// Waits for the processes returned by [`Shell::spawn_pipeline`], and returns the status of the last one.
+fn wait_pipeline(children: Vec<Child>, last: Result<Child, i32>) -> i32 {
+    for mut child in children {
+        wait_child(&mut child);
+    }
+
+    match last {
+        Ok(mut child) => wait_child(&mut child),
+        Err(status) => status,
+    }
+}
+
+/// Waits for the process returned by [`Shell::spawn`], and returns its status.
+fn wait_spawned(res: Result<Child, i32>) -> i32 {
+    match res {
+        Ok(mut child) => wait_child(&mut child),
+        Err(status) => status,
+    }
+}
+
+fn wait_child(child: &mut Child) -> i32 {
+    match child.wait() {
+        Ok(status) => status_code(status),
+        Err(e) => {
+            eprintln!("Error waiting for child: {e}");
+            1
+        }
+    }
+}
+
+/// A pipeline that was started in the background with `&`.
+struct Job {
+    /// The number of the job, as in `%1`.
+    id: usize,
+    /// The pipeline as it was written, shown by `jobs`.
+    cmd: String,
+    /// The processes of the pipeline, other than the last one, that have not been waited for.
+    children: Vec<Child>,
+    /// The last process of the pipeline, until it has been waited for.
+    last: Option<Child>,
+    /// The status of the last process, once it has exited (or if it could not be spawned).
+    status: Option<i32>,
+}
+
+impl Job {
+    /// Checks which processes of the job have exited, without waiting, and returns whether all of them have.
+    fn poll(&mut self) -> bool {
+        self.children
+            .retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+        if let Some(last) = &mut self.last {
+            match last.try_wait() {
+                Ok(None) => {}
+                Ok(Some(status)) => {
+                    self.status = Some(status_code(status));
+                    self.last = None;
+                }
+                Err(e) => {
+                    eprintln!("Error waiting for child: {e}");
+                    self.status = Some(1);
+                    self.last = None;
+                }
+            }
+        }
+        self.children.is_empty() && self.last.is_none()
+    }
+
+    /// Waits for every process of the job, and returns its status.
+    fn wait(&mut self) -> i32 {
+        for mut child in self.children.drain(..) {
+            wait_child(&mut child);
+        }
+        if let Some(mut last) = self.last.take() {
+            self.status = Some(wait_child(&mut last));
+        }
+        self.status.unwrap_or(0)
+    }
+
+    fn state(&self) -> Cow<'static, str> {
+        match self.status {
+            _ if !self.children.is_empty() || self.last.is_some() => Cow::Borrowed("Running"),
+            Some(0) | None => Cow::Borrowed("Done"),
+            Some(status) => Cow::Owned(format!("Exit {status}")),
+        }
+    }
+}
+
+/// A shell variable.
+#[derive(Clone, PartialEq)]
+struct Var {
+    /// `None` if the variable has been exported, but not set.
+    value: Option<String>,
+    /// Whether the variable is in the environment of the shell, and so passed to every command.
+    exported: bool,
+}
+
+/// The state of the shell: its variables, and the status of the last command.
+pub struct Shell {
+    /// The name of the shell or script, as `$0`.
+    name: String,
+    vars: BTreeMap<String, Var>,
+    /// The positional parameters, `$1` onwards.
+    params: Vec<String>,
+    /// The status of the last pipeline, as `$?`.
+    status: i32,
+    /// The source text of the body of each function.
+    functions: BTreeMap<String, Rc<str>>,
+    /// How many functions and sourced scripts are running, which `return` can leave.
+    depth: usize,
+    /// Set by `return` until the innermost function or sourced script has been left.
+    returning: bool,
+    /// How many command substitutions are running, which `exit` leaves instead of the shell.
+    substitutions: usize,
+    /// Set by `exit` in a command substitution, along with `returning`, until the substitution has been left.
+    exiting: bool,
+    /// Whether job numbers are printed when background jobs start.
+    interactive: bool,
+    /// The background jobs that have not been reported as finished yet.
+    jobs: Vec<Job>,
+    /// The process id of the last command of the most recent background job, as `$!`.
+    last_job_pid: Option<u64>,
+    /// The lines entered at the interactive prompt.
+    history: History,
+    /// The output of the command substitution that is running, while the output of commands goes to it.
+    capture: Option<String>,
+    /// The status of the last command substitution in the command that is running, if it had any.
+    substituted: Option<i32>,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shell {
+    /// Creates a shell whose variables are the environment of the current process.
+    pub fn new() -> Self {
+        Self {
+            name: String::from(ministd::start::args().next().unwrap_or("minish")),
+            vars: ministd::start::vars()
+                .map(|(key, val)| {
+                    let var = Var {
+                        value: Some(val.into_owned()),
+                        exported: true,
+                    };
+                    (key.into_owned(), var)
+                })
+                .collect(),
+            params: Vec::new(),
+            status: 0,
+            functions: BTreeMap::new(),
+            depth: 0,
+            returning: false,
+            substitutions: 0,
+            exiting: false,
+            interactive: false,
+            jobs: Vec::new(),
+            last_job_pid: None,
+            history: History::default(),
+            capture: None,
+            substituted: None,
+        }
+    }
+
+    /// The status of the last pipeline that ran, as `$?`.
+    pub fn status(&self) -> i32 {
+        self.status
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sets `$0`, which is used as the name of the shell in error messages.
+    pub fn set_name(&mut self, name: &str) {
+        self.name = String::from(name);
+    }
+
+    /// Makes the shell print the number and process id of each background job as it starts.
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+    }
+
+    /// The names of the functions that have been defined.
+    pub fn function_names(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(String::as_str)
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    /// Sets the positional parameters, `$1` onwards.
+    pub fn set_params(&mut self, params: Vec<String>) {
+        self.params = params;
+    }
+
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name)?.value.as_deref()
+    }
+
+    /// Sets the variable `name`, which is also updated in the environment if it has been exported.
+    pub fn set_var(&mut self, name: &str, val: String) {
+        let var = self.vars.entry(String::from(name)).or_insert(Var {
+            value: None,
+            exported: false,
+        });
+        if var.exported {
+            ministd::start::set_var(name, &val);
+        }
+        var.value = Some(val);
+    }
+
+    /// Removes the variable `name`, including from the environment.
+    pub fn remove_var(&mut self, name: &str) {
+        if let Some(var) = self.vars.remove(name)
+            && var.exported
+        {
+            ministd::start::remove_var(name);
+        }
+    }
+
+    /// Adds the variable `name` to the environment, which it keeps being updated in.
+    pub fn export_var(&mut self, name: &str) {
+        let var = self.vars.entry(String::from(name)).or_insert(Var {
+            value: None,
+            exported: false,
+        });
+        if let Some(val) = &var.value {
+            ministd::start::set_var(name, val);
+        }
+        var.exported = true;
+    }
+
+    /// Calls `f` with the directories in `PATH`, which are only opened again when `PATH` changes.
+    pub(crate) fn with_path_dirs<R>(&self, f: impl FnOnce(&[(String, File)]) -> R) -> R {
+        let path = self.var("PATH");
+        let mut cache = PATH.borrow_mut();
+        if cache.path.as_deref() != path {
+            cache.dirs = path
+                .into_iter()
+                .flat_map(|v| v.split(':'))
+                .filter_map(|v| {
+                    OpenOptions::new()
+                        .read(true)
+                        .directory(true)
+                        .open(v)
+                        .ok()
+                        .map(|dir| (String::from(v), dir))
+                })
+                .collect();
+            cache.path = path.map(String::from);
+        }
+        f(&cache.dirs)
+    }
+
+    /// Builds the [`Command`] that runs `argv`, which must not be empty, with the variables assigned by `line`.
+    fn command_for(&mut self, line: &ShellLine, argv: &[String]) -> Command {
+        let n = &argv[0];
+        let mut cmd = Command::new(n);
+        cmd.args(&argv[1..]);
+        if !n.contains('/') {
+            self.with_path_dirs(|dirs| {
+                cmd.search_dirs(dirs.iter().map(|(_, dir)| dir.as_raw_handle()));
+            });
+        }
+        for var in &line.env {
+            cmd.env(var.key, &self.expand_word(&var.val));
+        }
+        cmd
+    }
+
+    /// The value of the parameter `name`, which is either a variable or one of the special parameters.
+    fn param(&self, name: &str) -> Option<Cow<'_, str>> {
+        match name {
+            "?" => Some(Cow::Owned(format!("{}", self.status))),
+            "$" => Some(Cow::Owned(format!("{}", process::id()))),
+            "#" => Some(Cow::Owned(format!("{}", self.params.len()))),
+            "!" => self.last_job_pid.map(|pid| Cow::Owned(format!("{pid}"))),
+            "0" => Some(Cow::Borrowed(&self.name)),
+            n if n.starts_with(|c: char| c.is_ascii_digit()) => n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| self.params.get(n.checked_sub(1)?))
+                .map(|val| Cow::Borrowed(val.as_str())),
+            n => self.var(n).map(Cow::Borrowed),
+        }
+    }
+
+    /// Expands `word` into pieces of text, each with whether it was quoted.
+    fn expand_pieces<'w>(&mut self, word: &'w Word, out: &mut Vec<(Cow<'w, str>, bool)>) {
+        for part in &word.parts {
+            match part {
+                WordPart::Literal(s) => out.push((Cow::Borrowed(*s), false)),
+                WordPart::Quoted(s) => out.push((Cow::Borrowed(&**s), true)),
+                WordPart::Param {
+                    name,
+                    default,
+                    quoted,
+                } => match (self.param(name).map(Cow::into_owned), default) {
+                    (Some(val), None) => out.push((Cow::Owned(val), *quoted)),
+                    (Some(val), Some(_)) if !val.is_empty() => out.push((Cow::Owned(val), *quoted)),
+                    (_, Some(default)) => self.expand_pieces(default, out),
+                    (None, None) => {}
+                },
+                WordPart::Command { src, quoted } => {
+                    let val = self.substitute(src);
+                    out.push((Cow::Owned(val), *quoted));
+                }
+            }
+        }
+    }
+
+    /// Expands the parameters and command substitutions in `word`.
+    pub fn expand_word(&mut self, word: &Word) -> String {
+        let mut pieces = Vec::new();
+        self.expand_pieces(word, &mut pieces);
+        pieces.iter().map(|(s, _)| &**s).collect()
+    }
+
+    /// Expands `word` into a pattern for [`pattern::matches`], in which quoted text only matches itself.
+    pub fn expand_pattern(&mut self, word: &Word) -> String {
+        let mut pieces = Vec::new();
+        self.expand_pieces(word, &mut pieces);
+        pattern_of(&pieces)
+    }
+
+    /// Expands `words` into the fields of a command.
+    ///
+    /// Like in other shells, unquoted text is split into separate fields at whitespace,
+    /// and an unquoted word that expands to nothing is removed, rather than becoming an empty field.
+    /// A field with unquoted wildcards is replaced by the paths that match it, in order,
+    /// or kept as it is if there are none.
+    pub fn expand_fields<'w, 'a: 'w>(
+        &mut self,
+        words: impl IntoIterator<Item = &'w Word<'a>>,
+    ) -> Vec<String> {
+        let mut fields = Vec::new();
+        for word in words {
+            let mut pieces = Vec::new();
+            self.expand_pieces(word, &mut pieces);
+            for pieces in split_fields(pieces) {
+                let pattern = pattern_of(&pieces);
+                if pattern::has_wildcards(&pattern) {
+                    let paths = pattern::glob(&pattern);
+                    if !paths.is_empty() {
+                        fields.extend(paths);
+                        continue;
+                    }
+                }
+                fields.push(pieces.iter().map(|(s, _)| &**s).collect());
+            }
+        }
+        fields
+    }
+
+    /// Spawns `argv` with the given standard handles, after applying the redirections of `line`.
+    ///
+    /// Errors are reported here. If no child is spawned, returns the status of the command instead.
+    fn spawn(
+        &mut self,
+        line: &ShellLine,
+        argv: &[String],
+        stdin: HandlePtr<IOHandle>,
+        stdout: HandlePtr<IOHandle>,
+    ) -> Result<Child, i32> {
+        let Some(redirected) = Redirected::open(self, line, stdin, stdout) else {
+            return Err(1);
+        };
+        let Some(name) = argv.first() else {
+            return Err(0);
+        };
+
+        let mut cmd = self.command_for(line, argv);
+        redirected.apply(&mut cmd);
+        cmd.spawn().map_err(|e| {
+            eprintln!("Error spawning {name}: {e}");
+            STATUS_NOT_SPAWNED
+        })
+    }
+
+    /// The fields of the command in `line`, after expansion.
+    fn argv(&mut self, line: &ShellLine) -> Vec<String> {
+        self.expand_fields(line.command.iter().chain(&line.args))
+    }
+
+    /// Runs the commands in `src` line by line, and returns the status of the last one.
+    ///
+    /// A command that spans several lines is run once all of its lines have been read.
+    /// Stops at the first syntax error, with status `2`.
+    pub fn exec_source(&mut self, src: &str) -> i32 {
+        let mut pending = String::new();
+        for line in src.split_inclusive('\n') {
+            pending.push_str(line);
+            match parse_shell(split_shell(&pending)) {
+                Ok(list) => {
+                    self.exec_list(&list);
+                    pending.clear();
+                    if self.returning {
+                        return self.status;
+                    }
+                }
+                Err(ParseError::Incomplete) => {}
+                Err(e) => {
+                    eprintln!("{}: {e}", self.name);
+                    self.status = 2;
+                    return self.status;
+                }
+            }
+        }
+
+        if !pending.is_empty() {
+            eprintln!("{}: {}", self.name, ParseError::Incomplete);
+            self.status = 2;
+        }
+        self.status
+    }
+
+    /// Runs each chain in `list` in turn, and returns the status of the last one.
+    ///
+    /// Stops early if `return` is run.
+    pub fn exec_list(&mut self, list: &List) -> i32 {
+        for chain in &list.items {
+            if chain.background {
+                self.exec_background(chain);
+            } else {
+                self.exec_and_or(chain);
+            }
+            if self.returning {
+                break;
+            }
+        }
+        self.status
+    }
+
+    /// Runs the pipelines of `chain` that its connectors select, and returns the status of the last one that ran.
+    pub fn exec_and_or(&mut self, chain: &AndOr) -> i32 {
+        self.status = self.exec_pipeline(&chain.first);
+        for (conn, pipeline) in &chain.rest {
+            if self.returning {
+                break;
+            }
+            let run = match conn {
+                Connector::And => self.status == 0,
+                Connector::Or => self.status != 0,
+            };
+            if run {
+                self.status = self.exec_pipeline(pipeline);
+            }
+        }
+        self.status
+    }
+
+    /// Runs every command in `pipeline` concurrently, and waits for all of them.
+    ///
+    /// Returns the status of the last command.
+    /// Builtins and compound commands are only recognized in pipelines of a single command.
+    pub fn exec_pipeline(&mut self, pipeline: &Pipeline) -> i32 {
+        match &pipeline.commands[..] {
+            [Stage::Simple(line)] => return self.exec_line(line),
+            [Stage::Compound(compound)] => return self.exec_compound(compound),
+            [Stage::Function(function)] => {
+                self.functions
+                    .insert(String::from(function.name), Rc::from(function.src));
+                return 0;
+            }
+            _ => {}
+        }
+
+        self.with_output(
+            |shell, stdout| shell.spawn_pipeline(pipeline, stdout),
+            |(children, last)| wait_pipeline(children, last),
+        )
+    }
+
+    /// Runs `compound`, and returns the status of the last command that ran in it.
+    ///
+    /// Conditions succeed if the status of their last command is `0`.
+    pub fn exec_compound(&mut self, compound: &Compound) -> i32 {
+        match compound {
+            Compound::Group(list) => self.exec_list(list),
+            Compound::If {
+                branches,
+                otherwise,
+            } => {
+                for (cond, body) in branches {
+                    let status = self.exec_list(cond);
+                    if self.returning {
+                        return status;
+                    }
+                    if status == 0 {
+                        return self.exec_list(body);
+                    }
+                }
+                match otherwise {
+                    Some(otherwise) => self.exec_list(otherwise),
+                    None => 0,
+                }
+            }
+            Compound::While { cond, body, until } => {
+                let mut status = 0;
+                loop {
+                    let cond = self.exec_list(cond);
+                    if self.returning {
+                        return cond;
+                    }
+                    if (cond == 0) == *until {
+                        break status;
+                    }
+                    status = self.exec_list(body);
+                    if self.returning {
+                        break status;
+                    }
+                }
+            }
+            Compound::For { name, words, body } => {
+                let fields = match words {
+                    Some(words) => self.expand_fields(words),
+                    None => self.params.clone(),
+                };
+                let mut status = 0;
+                for field in fields {
+                    self.set_var(name, field);
+                    status = self.exec_list(body);
+                    if self.returning {
+                        break;
+                    }
+                }
+                status
+            }
+            Compound::Case { word, arms } => {
+                let subject = self.expand_word(word);
+                for arm in arms {
+                    for pat in &arm.patterns {
+                        if pattern::matches(&self.expand_pattern(pat), &subject) {
+                            return self.exec_list(&arm.body);
+                        }
+                    }
+                }
+                0
+            }
+        }
+    }
+
+    /// Spawns every command in `pipeline`, connecting each one's stdout to the next one's stdin,
+    /// and the last one's stdout to `stdout`.
+    ///
+    /// Returns the processes of all but the last command, and the process of the last command
+    /// (or its status, if it could not be spawned).
+    fn spawn_pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        stdout: HandlePtr<IOHandle>,
+    ) -> (Vec<Child>, Result<Child, i32>) {
+        let mut children = Vec::new();
+        let mut last = Err(1);
+        let mut stdin = None;
+        let len = pipeline.commands.len();
+
+        for (i, stage) in pipeline.commands.iter().enumerate() {
+            // The shell's ends of the pipes stay open until this stage has been spawned
+            let stage_stdin: Option<PipeReader> = stdin.take();
+            let stage_stdout = if i + 1 < len {
+                match io::pipe() {
+                    Ok((read, write)) => {
+                        stdin = Some(read);
+                        Some(write)
+                    }
+                    Err(e) => {
+                        eprintln!("Error creating pipe: {e}");
+                        break;
+                    }
+                }
+            } else {
+                None
+            };
+
+            let res = match stage {
+                Stage::Simple(line) => {
+                    let argv = self.argv(line);
+                    self.spawn(
+                        line,
+                        &argv,
+                        stage_stdin
+                            .as_ref()
+                            .map_or(io::stdin().as_raw_handle(), PipeReader::as_raw_handle),
+                        stage_stdout
+                            .as_ref()
+                            .map_or(stdout, PipeWriter::as_raw_handle),
+                    )
+                }
+                Stage::Compound(_) | Stage::Function(_) => {
+                    eprintln!(
+                        "{}: compound commands cannot be part of a pipeline or run in the background",
+                        self.name
+                    );
+                    Err(1)
+                }
+            };
+
+            if i + 1 == len {
+                last = res;
+            } else {
+                children.extend(res);
+            }
+        }
+
+        (children, last)
+    }
+
+    /// Starts the pipeline of `chain` without waiting for it, and adds it to the job table.
+    ///
+    /// Every command is spawned, even if it has the name of a builtin or a function.
+    /// Chains of more than one pipeline can't run in the background, since the shell can't fork itself.
+    pub fn exec_background(&mut self, chain: &AndOr) -> i32 {
+        if !chain.rest.is_empty() {
+            eprintln!(
+                "{}: only a single pipeline can run in the background",
+                self.name
+            );
+            self.status = 1;
+            return 1;
+        }
+
+        let (children, last) = self.spawn_pipeline(&chain.first, io::stdout().as_raw_handle());
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        let (last, status) = match last {
+            Ok(child) => (Some(child), None),
+            Err(status) => (None, Some(status)),
+        };
+        self.last_job_pid = last.as_ref().map(Child::id);
+        if self.interactive {
+            match &self.last_job_pid {
+                Some(pid) => eprintln!("[{id}] {pid}"),
+                None => eprintln!("[{id}]"),
+            }
+        }
+
+        self.jobs.push(Job {
+            id,
+            cmd: format!("{}", chain.first),
+            children,
+            last,
+            status,
+        });
+        self.status = 0;
+        0
+    }
+
+    /// Runs the commands in `src`, and returns what they write to stdout, without any trailing newlines.
+    ///
+    /// Other shells run the commands in a subshell, so that they can't change the shell itself.
+    /// This shell can't fork itself, so it puts back its variables, positional parameters, functions
+    /// and current directory afterwards instead. `$?` becomes the status of the commands,
+    /// and `exit` only leaves the substitution.
+    fn substitute(&mut self, src: &str) -> String {
+        let list = match parse_shell(split_shell(src)) {
+            Ok(list) => list,
+            Err(e) => {
+                eprintln!("{}: {e}", self.name);
+                self.status = 2;
+                self.substituted = Some(2);
+                return String::new();
+            }
+        };
+
+        let vars = self.vars.clone();
+        let params = self.params.clone();
+        let functions = self.functions.clone();
+        let cwd = env::current_dir().ok();
+
+        let outer = self.capture.replace(String::new());
+        self.substitutions += 1;
+        let status = self.exec_list(&list);
+        self.substitutions -= 1;
+        let mut out = core::mem::replace(&mut self.capture, outer).unwrap_or_default();
+        self.returning = false;
+        self.exiting = false;
+
+        if let Some(cwd) = cwd
+            && env::current_dir().ok().as_ref() != Some(&cwd)
+            && let Err(e) = env::set_current_dir(&cwd)
+        {
+            eprintln!("{}: {cwd}: {e}", self.name);
+        }
+        self.reset_vars(vars);
+        self.params = params;
+        self.functions = functions;
+        self.status = status;
+        self.substituted = Some(status);
+
+        out.truncate(out.trim_end_matches('\n').len());
+        out
+    }
+
+    /// Runs `run` with the handle that the output of a command goes to, and then `wait` with what it returns.
+    ///
+    /// That is the shell's stdout, unless a command substitution is running, in which case it is a pipe
+    /// that is read into the substitution in between, so that children never block on a full pipe.
+    fn with_output<T>(
+        &mut self,
+        run: impl FnOnce(&mut Self, HandlePtr<IOHandle>) -> T,
+        wait: impl FnOnce(T) -> i32,
+    ) -> i32 {
+        if self.capture.is_none() {
+            let res = run(self, io::stdout().as_raw_handle());
+            return wait(res);
+        }
+
+        let (mut read, write) = match io::pipe() {
+            Ok(pipe) => pipe,
+            Err(e) => {
+                eprintln!("Error creating pipe: {e}");
+                return 1;
+            }
+        };
+        let res = run(self, write.as_raw_handle());
+        // The output ends once the children, which have their own copies of the write end, have all exited
+        drop(write);
+        if let Some(out) = &mut self.capture
+            && let Err(e) = ReadEx::read_to_string(&mut read, out)
+        {
+            eprintln!("Error reading command output: {}", Error::from(e));
+        }
+        wait(res)
+    }
+
+    /// Prints the background jobs that have finished since the last call, and forgets them.
+    pub fn notify_jobs(&mut self) {
+        self.jobs.retain_mut(|job| {
+            if !job.poll() {
+                return true;
+            }
+            println!("[{}]  {:<10}  {}", job.id, job.state(), job.cmd);
+            false
+        });
+    }
+
+    /// Runs the simple command in `line`, and returns its status.
+    ///
+    /// Builtins and functions run in the shell itself, with the standard handles of the shell
+    /// pointed at the targets of the redirections for as long as they run.
+    pub fn exec_line(&mut self, line: &ShellLine) -> i32 {
+        self.substituted = None;
+        let argv = self.argv(line);
+        let Some(name) = argv.first() else {
+            for var in &line.env {
+                let val = self.expand_word(&var.val);
+                self.set_var(var.key, val);
+            }
+            let status = self.with_output(
+                |shell, stdout| shell.spawn(line, &argv, io::stdin().as_raw_handle(), stdout),
+                wait_spawned,
+            );
+            // Like in other shells, a command of only assignments has the status of its last command substitution
+            return match status {
+                0 => self.substituted.unwrap_or(0),
+                status => status,
+            };
+        };
+
+        // Builtins write their output with `write_out`, and functions and sourced scripts run commands,
+        // which decide where their own output goes, so none of them need a pipe for a command substitution
+        if BUILTINS.contains(&name.as_str()) || self.functions.contains_key(name.as_str()) {
+            let redirects_stdout = line
+                .redirects
+                .iter()
+                .any(|redirect| matches!(redirect, Redirect::Stdout { .. }));
+            // Output that is redirected doesn't go to a command substitution that's running
+            let capture = if redirects_stdout {
+                self.capture.take()
+            } else {
+                None
+            };
+            let status = self.exec_in_shell(line, &argv, io::stdout().as_raw_handle());
+            if redirects_stdout {
+                self.capture = capture;
+            }
+            return status;
+        }
+
+        self.with_output(
+            |shell, stdout| shell.spawn(line, &argv, io::stdin().as_raw_handle(), stdout),
+            wait_spawned,
+        )
+    }
+
+    /// Writes `out`, the output of a builtin, to the command substitution that is running, or else to stdout.
+    fn write_out(&mut self, out: &str) {
+        match &mut self.capture {
+            Some(capture) => capture.push_str(out),
+            None => print!("{out}"),
+        }
+    }
+
+    /// Runs the builtin or function in `argv` in the shell itself, with the redirections and assignments of `line`.
+    ///
+    /// `stdout` is where its output goes, unless that's redirected.
+    fn exec_in_shell(
+        &mut self,
+        line: &ShellLine,
+        argv: &[String],
+        stdout: HandlePtr<IOHandle>,
+    ) -> i32 {
+        let Some(redirected) = Redirected::open(self, line, io::stdin().as_raw_handle(), stdout)
+        else {
+            return 1;
+        };
+        let saved = self.assign_temporarily(&line.env);
+        let stdio = redirected.apply_to_shell();
+        let status = self.exec_builtin(argv);
+        drop(stdio);
+        self.restore_vars(saved);
+        status
+    }
+
+    /// Runs the builtin or function named by `argv[0]`.
+    fn exec_builtin(&mut self, argv: &[String]) -> i32 {
+        match argv[0].as_str() {
+            "return" => self.builtin_return(&argv[1..]),
+            c @ ("exit" | "logout") => {
+                let status = match argv.get(1).map(|v| v.parse()) {
+                    Some(Ok(status)) => status,
+                    Some(Err(e)) => {
+                        eprintln!("{c}: {e}");
+                        return 2;
+                    }
+                    None => self.status,
+                };
+                if self.substitutions > 0 {
+                    // Like in a subshell, only the command substitution ends
+                    self.exiting = true;
+                    self.returning = true;
+                    return status;
+                }
+                exit(status)
+            }
+            "export" => self.builtin_export(&argv[1..]),
+            "unset" => self.builtin_unset(&argv[1..]),
+            "set" => self.builtin_set(&argv[1..]),
+            "cd" => self.builtin_cd(&argv[1..]),
+            "pwd" => self.builtin_pwd(),
+            "jobs" => self.builtin_jobs(),
+            "history" => self.builtin_history(),
+            "wait" => self.builtin_wait(&argv[1..]),
+            "fg" => self.builtin_fg(&argv[1..]),
+            c @ ("source" | ".") => self.builtin_source(c, &argv[1..]),
+            name => self.call_function(name, &argv[1..]),
+        }
+    }
+
+    /// Sets the variables assigned before a builtin or function, for as long as it runs.
+    ///
+    /// Like in other shells, they are exported, so that the commands it runs see them too.
+    /// Returns the variables they replaced, for [`Shell::restore_vars`].
+    fn assign_temporarily(&mut self, env: &[EnvVar]) -> Vec<(String, Option<Var>)> {
+        let vals: Vec<String> = env.iter().map(|var| self.expand_word(&var.val)).collect();
+        let mut saved = Vec::new();
+        for (var, val) in env.iter().zip(vals) {
+            ministd::start::set_var(var.key, &val);
+            let new = Var {
+                value: Some(val),
+                exported: true,
+            };
+            saved.push((
+                String::from(var.key),
+                self.vars.insert(String::from(var.key), new),
+            ));
+        }
+        saved
+    }
+
+    /// Puts back the variables replaced by [`Shell::assign_temporarily`], including in the environment.
+    fn restore_vars(&mut self, saved: Vec<(String, Option<Var>)>) {
+        for (name, var) in saved.into_iter().rev() {
+            match &var {
+                Some(Var {
+                    value: Some(val),
+                    exported: true,
+                }) => ministd::start::set_var(&name, val),
+                _ => ministd::start::remove_var(&name),
+            }
+            match var {
+                Some(var) => self.vars.insert(name, var),
+                None => self.vars.remove(&name),
+            };
+        }
+    }
+
+    /// Puts back `vars`, a copy of the variables taken earlier, including in the environment.
+    fn reset_vars(&mut self, mut vars: BTreeMap<String, Var>) {
+        let mut changed: Vec<String> = self
+            .vars
+            .keys()
+            .chain(vars.keys())
+            .filter(|name| self.vars.get(*name) != vars.get(*name))
+            .cloned()
+            .collect();
+        changed.sort();
+        changed.dedup();
+        let saved = changed
+            .into_iter()
+            .map(|name| {
+                let var = vars.remove(&name);
+                (name, var)
+            })
+            .collect();
+        self.restore_vars(saved);
+    }
+
+    /// Runs the function `name`, with `args` as its positional parameters.
+    fn call_function(&mut self, name: &str, args: &[String]) -> i32 {
+        let src = self.functions[name].clone();
+        let body = match parse_shell(split_shell(&src)) {
+            Ok(body) => body,
+            Err(e) => {
+                eprintln!("{}: {name}: {e}", self.name);
+                return 2;
+            }
+        };
+
+        let params = core::mem::replace(&mut self.params, args.to_vec());
+        self.depth += 1;
+        let status = self.exec_list(&body);
+        self.depth -= 1;
+        self.returning = self.exiting;
+        self.params = params;
+        status
+    }
+
+    /// `export [NAME[=VALUE]]...`: exports each `NAME`, after setting it to `VALUE`.
+    ///
+    /// Without arguments, prints the exported variables.
+    fn builtin_export(&mut self, args: &[String]) -> i32 {
+        if args.is_empty() {
+            let mut out = String::new();
+            for (name, var) in &self.vars {
+                match &var.value {
+                    Some(val) if var.exported => out.push_str(&format!("export {name}={val}\n")),
+                    None if var.exported => out.push_str(&format!("export {name}\n")),
+                    _ => {}
+                }
+            }
+            self.write_out(&out);
+            return 0;
+        }
+
+        let mut status = 0;
+        for arg in args {
+            let (name, val) = match arg.split_once('=') {
+                Some((name, val)) => (name, Some(val)),
+                None => (&**arg, None),
+            };
+            if !is_valid_name(name) {
+                eprintln!("export: `{arg}': not a valid identifier");
+                status = 1;
+                continue;
+            }
+            if let Some(val) = val {
+                self.set_var(name, String::from(val));
+            }
+            self.export_var(name);
+        }
+        status
+    }
+
+    /// `unset NAME...`: removes each variable `NAME`.
+    fn builtin_unset(&mut self, args: &[String]) -> i32 {
+        let mut status = 0;
+        for name in args {
+            if !is_valid_name(name) {
+                eprintln!("unset: `{name}': not a valid identifier");
+                status = 1;
+                continue;
+            }
+            self.remove_var(name);
+        }
+        status
+    }
+
+    /// `cd [DIR]`: changes the current directory to `DIR`.
+    ///
+    /// `DIR` defaults to `$HOME`. If `DIR` is `-`, changes back to `$OLDPWD` and prints it.
+    fn builtin_cd(&mut self, args: &[String]) -> i32 {
+        let (dir, print) = match args.first().map(String::as_str) {
+            None => match self.var("HOME") {
+                Some(home) => (String::from(home), false),
+                None => {
+                    eprintln!("cd: HOME not set");
+                    return 1;
+                }
+            },
+            Some("-") => match self.var("OLDPWD") {
+                Some(old) => (String::from(old), true),
+                None => {
+                    eprintln!("cd: OLDPWD not set");
+                    return 1;
+                }
+            },
+            Some(dir) => (String::from(dir), false),
+        };
+
+        let old = env::current_dir();
+        if let Err(e) = env::set_current_dir(&dir) {
+            eprintln!("cd: {dir}: {e}");
+            return 1;
+        }
+
+        if let Ok(old) = old {
+            self.set_var("OLDPWD", old);
+        }
+        // `set_current_dir` has already updated `PWD` in the environment, so only the shell's copy is left
+        match env::current_dir() {
+            Ok(pwd) => {
+                if print {
+                    self.write_out(&format!("{pwd}\n"));
+                }
+                let var = Var {
+                    value: Some(pwd),
+                    exported: true,
+                };
+                self.vars.insert(String::from("PWD"), var);
+            }
+            Err(_) => {
+                self.vars.remove("PWD");
+            }
+        }
+        0
+    }
+
+    /// `set [--] [ARG]...`: sets the positional parameters to the `ARG`s.
+    ///
+    /// Without arguments, prints every variable that is set. Options aren't supported.
+    fn builtin_set(&mut self, args: &[String]) -> i32 {
+        if args.is_empty() {
+            let mut out = String::new();
+            for (name, var) in &self.vars {
+                if let Some(val) = &var.value {
+                    out.push_str(&format!("{name}={val}\n"));
+                }
+            }
+            self.write_out(&out);
+            return 0;
+        }
+
+        let args = match args.split_first() {
+            Some((first, rest)) if first == "--" => rest,
+            Some((first, _)) if first.starts_with(['-', '+']) => {
+                eprintln!("set: {first}: invalid option");
+                eprintln!("set: usage: set [--] [ARG]...");
+                return 2;
+            }
+            _ => args,
+        };
+        self.params = args.to_vec();
+        0
+    }
+
+    /// `return [N]`: leaves the innermost function or sourced script, with status `N` (or `$?`).
+    fn builtin_return(&mut self, args: &[String]) -> i32 {
+        if self.depth == 0 {
+            eprintln!("return: can only be used in a function or sourced script");
+            return 1;
+        }
+        let status = match args.first().map(|v| v.parse()) {
+            Some(Ok(status)) => status,
+            Some(Err(e)) => {
+                eprintln!("return: {e}");
+                2
+            }
+            None => self.status,
+        };
+        self.returning = true;
+        status
+    }
+
+    /// Finds the job that `spec` refers to: `%N` for job number `N`, or `%%` or `%+` for the most recent job.
+    fn find_job(&self, c: &str, spec: &str) -> Option<usize> {
+        let idx = match spec {
+            "%%" | "%+" => self.jobs.len().checked_sub(1),
+            _ => spec
+                .strip_prefix('%')
+                .and_then(|id| id.parse::<usize>().ok())
+                .and_then(|id| self.jobs.iter().position(|job| job.id == id)),
+        };
+        if idx.is_none() {
+            eprintln!("{c}: {spec}: no such job");
+        }
+        idx
+    }
+
+    /// `jobs`: prints the background jobs, and whether each one is still running.
+    ///
+    /// Finished jobs are forgotten once they have been printed.
+    fn builtin_jobs(&mut self) -> i32 {
+        let mut out = String::new();
+        self.jobs.retain_mut(|job| {
+            let done = job.poll();
+            out.push_str(&format!("[{}]  {:<10}  {}\n", job.id, job.state(), job.cmd));
+            !done
+        });
+        self.write_out(&out);
+        0
+    }
+
+    /// `history`: prints the lines entered at the interactive prompt, numbered from 1.
+    fn builtin_history(&mut self) -> i32 {
+        let mut out = String::new();
+        for (i, line) in self.history.entries().iter().enumerate() {
+            out.push_str(&format!("{:>5}  {line}\n", i + 1));
+        }
+        self.write_out(&out);
+        0
+    }
+
+    /// `wait [%N]...`: waits for each of the given jobs, and returns the status of the last one.
+    ///
+    /// Without arguments, waits for every background job and returns `0`.
+    fn builtin_wait(&mut self, args: &[String]) -> i32 {
+        if args.is_empty() {
+            for mut job in self.jobs.drain(..) {
+                job.wait();
+            }
+            return 0;
+        }
+
+        let mut status = 0;
+        for spec in args {
+            status = match self.find_job("wait", spec) {
+                Some(idx) => self.jobs.remove(idx).wait(),
+                None => 127,
+            };
+        }
+        status
+    }
+
+    /// `fg [%N]`: prints the job (by default the most recent one), and waits for it in the foreground.
+    fn builtin_fg(&mut self, args: &[String]) -> i32 {
+        let idx = match args.first() {
+            Some(spec) => self.find_job("fg", spec),
+            None => {
+                let idx = self.jobs.len().checked_sub(1);
+                if idx.is_none() {
+                    eprintln!("fg: no current job");
+                }
+                idx
+            }
+        };
+        let Some(idx) = idx else {
+            return 1;
+        };
+
+        let mut job = self.jobs.remove(idx);
+        self.write_out(&format!("{}\n", job.cmd));
+        job.wait()
+    }
+
+    /// `pwd`: prints the path of the current directory.
+    fn builtin_pwd(&mut self) -> i32 {
+        match env::current_dir() {
+            Ok(pwd) => {
+                self.write_out(&format!("{pwd}\n"));
+                0
+            }
+            Err(e) => {
+                eprintln!("pwd: {e}");
+                1
+            }
+        }
+    }
+
+    /// `source FILE [ARG]...` or `. FILE [ARG]...`: runs the commands in `FILE` in the current shell.
+    ///
+    /// If there are any `ARG`s, they are the positional parameters while `FILE` runs.
+    fn builtin_source(&mut self, c: &str, args: &[String]) -> i32 {
+        let Some((path, args)) = args.split_first() else {
+            eprintln!("{c}: filename argument required");
+            return 2;
+        };
+        let src = match read_script(path) {
+            Ok(src) => src,
+            Err(e) => {
+                eprintln!("{c}: {path}: {e}");
+                return 1;
+            }
+        };
+
+        let params =
+            (!args.is_empty()).then(|| core::mem::replace(&mut self.params, args.to_vec()));
+        self.depth += 1;
+        let status = self.exec_source(&src);
+        self.depth -= 1;
+        self.returning = self.exiting;
+        if let Some(params) = params {
+            self.params = params;
+        }
+        status
+    }
+}
+
+/// Splits the unquoted text in the pieces of an expanded word at whitespace, like other shells do with the default `IFS`.
+///
+/// Returns the pieces of each field. A field is only made of unquoted text if that text isn't empty,
+/// so a word whose expansions are all unquoted and empty has no fields.
+fn split_fields(pieces: Vec<(Cow<'_, str>, bool)>) -> Vec<Vec<(Cow<'_, str>, bool)>> {
+    let mut fields = Vec::new();
+    let mut field = Vec::new();
+    // Whether `field` has any quoted text, or any unquoted text that isn't whitespace
+    let mut started = false;
+    for (s, quoted) in pieces {
+        if quoted || !s.contains(char::is_whitespace) {
+            started |= quoted || !s.is_empty();
+            field.push((s, quoted));
+            continue;
+        }
+
+        let mut segments = s.split(char::is_whitespace);
+        // The text before the first whitespace continues the current field
+        if let Some(first) = segments.next().filter(|first| !first.is_empty()) {
+            field.push((Cow::Owned(String::from(first)), false));
+            started = true;
+        }
+        for segment in segments {
+            if started {
+                fields.push(core::mem::take(&mut field));
+                started = false;
+            }
+            if !segment.is_empty() {
+                field.push((Cow::Owned(String::from(segment)), false));
+                started = true;
+            }
+        }
+    }
+    if started {
+        fields.push(field);
+    }
+    fields
+}
+
+/// Joins the pieces of an expanded word into a pattern, escaping the quoted ones.
+fn pattern_of(pieces: &[(Cow<'_, str>, bool)]) -> String {
+    let mut out = String::new();
+    for (s, quoted) in pieces {
+        if *quoted {
+            pattern::escape_into(s, &mut out);
+        } else {
+            out.push_str(s);
+        }
+    }
+    out
+}
+
+/// Reads the whole of the script at `path`.
+pub fn read_script(path: &str) -> io::Result<String> {
+    let mut src = String::new();
+    let mut file = File::open(path)?;
+    ReadEx::read_to_string(&mut file, &mut src)?;
+    Ok(src)
 }
@@ -0,0 +1,129 @@
+use core::cell::{LazyCell, RefCell};
+
+use alloc::{string::String, vec::Vec};
+use lilium_sys::sys::{fs::FileHandle, handle::HandlePtr};
+
+use crate::{
+    fs::{File, OpenOptions},
+    helpers::AssertThreadSafe,
+    io::{Error, ErrorKind, Result},
+};
+
+/// The directory that relative paths are resolved against.
+struct CurrentDir {
+    /// `None` if the directory was reached by a relative path from an unknown directory.
+    path: Option<String>,
+    dir: File,
+}
+
+// SAFETY:
+// Like the globals in `start`, this is only used from the main thread
+static CURRENT_DIR: AssertThreadSafe<LazyCell<RefCell<Option<CurrentDir>>>> =
+    unsafe { AssertThreadSafe::new_unchecked(LazyCell::new(init_current_dir)) };
+
+/// The current directory starts out as `PWD`, which is how a parent passes its current directory to a child.
+fn init_current_dir() -> RefCell<Option<CurrentDir>> {
+    let cwd = crate::start::var("PWD")
+        .filter(|path| path.starts_with('/'))
+        .and_then(|path| {
+            let dir = dir_options().open_at(HandlePtr::null(), &path).ok()?;
+            Some(CurrentDir {
+                path: Some(normalize(&path, "")),
+                dir,
+            })
+        });
+    RefCell::new(cwd)
+}
+
+fn dir_options() -> OpenOptions {
+    let mut opts = OpenOptions::new();
+    opts.read(true).directory(true);
+    opts
+}
+
+/// Joins `path` onto `base` (unless `path` is absolute), and removes `.` and `..` components.
+///
+/// Like `cd` in other shells, `..` removes the previous component without resolving symlinks.
+fn normalize(base: &str, path: &str) -> String {
+    let mut components = Vec::new();
+    let base = if path.starts_with('/') { "" } else { base };
+    for c in base.split('/').chain(path.split('/')) {
+        match c {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            c => components.push(c),
+        }
+    }
+
+    let mut out = String::new();
+    for c in components {
+        out.push('/');
+        out.push_str(c);
+    }
+    if out.is_empty() {
+        out.push('/');
+    }
+    out
+}
+
+/// The handle of the current directory, or a null handle if the kernel's default is used.
+///
+/// The handle stays valid until the next call to [`set_current_dir`].
+pub(crate) fn current_dir_handle() -> HandlePtr<FileHandle> {
+    CURRENT_DIR
+        .borrow()
+        .as_ref()
+        .map_or(HandlePtr::null(), |cwd| cwd.dir.as_raw_handle())
+}
+
+/// Makes `path` absolute by joining it onto the current directory, if the path of the current directory is known.
+pub fn absolute(path: &str) -> Option<String> {
+    if path.starts_with('/') {
+        return Some(normalize("", path));
+    }
+    let cwd = CURRENT_DIR.borrow();
+    let base = cwd.as_ref()?.path.as_deref()?;
+    Some(normalize(base, path))
+}
+
+/// The path of the current directory.
+///
+/// Fails if the current process was started without an absolute `PWD`.
+pub fn current_dir() -> Result<String> {
+    match CURRENT_DIR
+        .borrow()
+        .as_ref()
+        .and_then(|cwd| cwd.path.clone())
+    {
+        Some(path) => Ok(path),
+        None => Err(Error::new_with_message(
+            ErrorKind::NotFound,
+            "The path of the current directory is unknown",
+        )),
+    }
+}
+
+/// Changes the current directory to `path`, which is resolved relative to the current directory.
+///
+/// `PWD` is updated, so that processes spawned afterwards start in the same directory.
+pub fn set_current_dir(path: &str) -> Result<()> {
+    let cwd = match absolute(path) {
+        Some(path) => CurrentDir {
+            dir: dir_options().open_at(HandlePtr::null(), &path)?,
+            path: Some(path),
+        },
+        None => CurrentDir {
+            dir: dir_options().open_at(current_dir_handle(), path)?,
+            path: None,
+        },
+    };
+
+    match &cwd.path {
+        Some(path) => crate::start::set_var("PWD", path),
+        None => crate::start::remove_var("PWD"),
+    }
+    *CURRENT_DIR.borrow_mut() = Some(cwd);
+    Ok(())
+}
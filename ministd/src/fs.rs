@@ -0,0 +1,510 @@
+use alloc::{rc::Rc, string::String};
+use genio::{Read, Write};
+use lilium_sys::{
+    sys::{
+        error::{FINISHED_ENUMERATE, INSUFFICIENT_LENGTH, UNSUPPORTED_OPERATION},
+        fs::{
+            ACCESS_CREATE, ACCESS_CREATE_EXCLUSIVE, ACCESS_READ, ACCESS_START_APPEND,
+            ACCESS_TRUNCATE, ACCESS_WRITE, CloseFile, DirectoryRecord, FILE_TYPE_DEVICE,
+            FILE_TYPE_DIRECTORY, FILE_TYPE_FIFO, FILE_TYPE_REGULAR, FILE_TYPE_SOCKET,
+            FILE_TYPE_SYMLINK, FileHandle, FileInfo, FileOpenOptions, GetFileInfo,
+            OP_DIRECTORY_ACCESS, OP_NO_LINK_RESOLUTION, OpenFile, ReadDirectory, ReserveFileSpace,
+            SetFileLength,
+        },
+        handle::HandlePtr,
+        io::{
+            IOHandle, IORead, IOSeek, IOWrite, MODE_BLOCKING, SEEK_FROM_CURRENT, SEEK_FROM_END,
+            SEEK_FROM_START,
+        },
+        kstr::{KCSlice, KStrCPtr, KStrPtr},
+    },
+    time::Duration,
+    uuid::Uuid,
+};
+
+use crate::io::{Error, ErrorKind, Result, Seek, SeekFrom};
+
+/// An open file (or directory), which is closed when dropped.
+pub struct File(HandlePtr<FileHandle>);
+
+impl File {
+    /// Opens the file at `path` for reading.
+    pub fn open(path: &str) -> Result<File> {
+        OpenOptions::new().read(true).open(path)
+    }
+
+    /// Opens the file at `path` for writing.
+    ///
+    /// The file is created if it does not exist, and truncated if it does.
+    pub fn create(path: &str) -> Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+    }
+
+    /// Takes ownership of a raw file handle.
+    ///
+    /// # Safety
+    /// `hdl` must be a valid, open file handle that is not closed elsewhere.
+    pub const unsafe fn from_raw_handle(hdl: HandlePtr<FileHandle>) -> Self {
+        Self(hdl)
+    }
+
+    /// The underlying handle, for use with `lilium_sys` functions that aren't wrapped here.
+    ///
+    /// The handle remains owned by `self`.
+    pub const fn as_raw_handle(&self) -> HandlePtr<FileHandle> {
+        self.0
+    }
+
+    /// Releases ownership of the underlying handle, which will no longer be closed on drop.
+    pub fn into_raw_handle(self) -> HandlePtr<FileHandle> {
+        let hdl = self.0;
+        core::mem::forget(self);
+        hdl
+    }
+
+    pub fn metadata(&self) -> Result<Metadata> {
+        let mut info = bytemuck::zeroed();
+        let res = unsafe { GetFileInfo(self.0, &mut info) };
+
+        if res < 0 {
+            Err(Error::from_raw_os_error(res))
+        } else {
+            Ok(Metadata(info))
+        }
+    }
+
+    /// Truncates or extends the file to `size` bytes. Extending the file fills it with zeroes.
+    ///
+    /// The cursor is not moved.
+    pub fn set_len(&self, size: u64) -> Result<()> {
+        let res = unsafe { SetFileLength(self.0, size) };
+
+        if res < 0 {
+            Err(Error::from_raw_os_error(res))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn io_handle(&self) -> HandlePtr<IOHandle> {
+        self.0.cast()
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe {
+            CloseFile(self.0);
+        }
+    }
+}
+
+impl Read for File {
+    type ReadError = Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = unsafe { IORead(self.io_handle(), buf.as_mut_ptr().cast(), buf.len() as _) };
+
+        if n < 0 {
+            Err(Error::from_raw_os_error(n))
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+impl Write for File {
+    type WriteError = Error;
+    type FlushError = Error;
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = unsafe { IOWrite(self.io_handle(), buf.as_ptr().cast(), buf.len() as _) };
+
+        if n < 0 {
+            Err(Error::from_raw_os_error(n))
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Reserves storage for `bytes` more bytes past the cursor, without changing the length of the file.
+    fn size_hint(&mut self, bytes: usize) {
+        if let Ok(pos) = self.stream_position() {
+            unsafe {
+                ReserveFileSpace(self.0, pos.saturating_add(bytes as u64));
+            }
+        }
+    }
+
+    fn uses_size_hint(&self) -> bool {
+        true
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (from, offset) = match pos {
+            SeekFrom::Start(n) => match i64::try_from(n) {
+                Ok(n) => (SEEK_FROM_START, n),
+                Err(_) => {
+                    return Err(Error::new_with_message(
+                        ErrorKind::InvalidInput,
+                        "Seek position is too large",
+                    ));
+                }
+            },
+            SeekFrom::End(n) => (SEEK_FROM_END, n),
+            SeekFrom::Current(n) => (SEEK_FROM_CURRENT, n),
+        };
+
+        let mut new_pos = 0;
+        let res = unsafe { IOSeek(self.io_handle(), from, offset, &mut new_pos) };
+
+        if res == UNSUPPORTED_OPERATION {
+            Err(Error::new_with_message(
+                ErrorKind::NotSeekable,
+                "Handle does not support seeking",
+            ))
+        } else if res < 0 {
+            Err(Error::from_raw_os_error(res))
+        } else {
+            Ok(new_pos)
+        }
+    }
+}
+
+/// Options for opening a [`File`], in the style of a builder.
+///
+/// All options are initially `false`.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    create: bool,
+    create_new: bool,
+    truncate: bool,
+    append: bool,
+    directory: bool,
+    no_follow: bool,
+}
+
+impl OpenOptions {
+    pub const fn new() -> Self {
+        Self {
+            read: false,
+            write: false,
+            create: false,
+            create_new: false,
+            truncate: false,
+            append: false,
+            directory: false,
+            no_follow: false,
+        }
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Creates the file if it does not already exist.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Creates the file, failing if it already exists.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// Truncates the file to length `0` when it is opened.
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Starts writing at the end of the file.
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    /// Opens a directory, rather than a regular file.
+    pub fn directory(&mut self, directory: bool) -> &mut Self {
+        self.directory = directory;
+        self
+    }
+
+    /// Opens a link itself, rather than the object it refers to.
+    pub fn no_follow(&mut self, no_follow: bool) -> &mut Self {
+        self.no_follow = no_follow;
+        self
+    }
+
+    /// Opens `path`, which is resolved relative to the [current directory][crate::env::current_dir].
+    pub fn open(&self, path: &str) -> Result<File> {
+        self.open_at(crate::env::current_dir_handle(), path)
+    }
+
+    /// Opens `path` relative to the directory `base`, or to the default base if `base` is null.
+    pub fn open_at(&self, base: HandlePtr<FileHandle>, path: &str) -> Result<File> {
+        let mut access_mode = 0;
+        if self.read {
+            access_mode |= ACCESS_READ;
+        }
+        if self.write || self.append {
+            access_mode |= ACCESS_WRITE;
+        }
+        if self.create {
+            access_mode |= ACCESS_CREATE;
+        }
+        if self.create_new {
+            access_mode |= ACCESS_CREATE_EXCLUSIVE;
+        }
+        if self.truncate {
+            access_mode |= ACCESS_TRUNCATE;
+        }
+        if self.append {
+            access_mode |= ACCESS_START_APPEND;
+        }
+
+        let mut op_mode = 0;
+        if self.directory {
+            op_mode |= OP_DIRECTORY_ACCESS;
+        }
+        if self.no_follow {
+            op_mode |= OP_NO_LINK_RESOLUTION;
+        }
+
+        let mut hdl = HandlePtr::null();
+        let res = unsafe {
+            OpenFile(
+                &mut hdl,
+                base,
+                KStrCPtr::from_str(path),
+                &FileOpenOptions {
+                    stream_override: KStrCPtr::empty(),
+                    access_mode,
+                    op_mode,
+                    create_acl: HandlePtr::null(),
+                    blocking_mode: MODE_BLOCKING,
+                    extended_options: KCSlice::empty(),
+                },
+            )
+        };
+
+        if res < 0 {
+            Err(Error::from_raw_os_error(res))
+        } else {
+            Ok(File(hdl))
+        }
+    }
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The kind of object a path refers to.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct FileType(u32);
+
+impl FileType {
+    pub const fn is_file(self) -> bool {
+        self.0 == FILE_TYPE_REGULAR
+    }
+
+    pub const fn is_dir(self) -> bool {
+        self.0 == FILE_TYPE_DIRECTORY
+    }
+
+    pub const fn is_symlink(self) -> bool {
+        self.0 == FILE_TYPE_SYMLINK
+    }
+
+    pub const fn is_device(self) -> bool {
+        self.0 == FILE_TYPE_DEVICE
+    }
+
+    pub const fn is_fifo(self) -> bool {
+        self.0 == FILE_TYPE_FIFO
+    }
+
+    pub const fn is_socket(self) -> bool {
+        self.0 == FILE_TYPE_SOCKET
+    }
+
+    pub const fn raw(self) -> u32 {
+        self.0
+    }
+}
+
+/// Information about a file, as returned by [`File::metadata`].
+#[derive(Copy, Clone)]
+pub struct Metadata(FileInfo);
+
+impl Metadata {
+    pub const fn file_type(&self) -> FileType {
+        FileType(self.0.file_type)
+    }
+
+    pub const fn is_dir(&self) -> bool {
+        self.file_type().is_dir()
+    }
+
+    pub const fn is_file(&self) -> bool {
+        self.file_type().is_file()
+    }
+
+    pub const fn len(&self) -> u64 {
+        self.0.size
+    }
+
+    /// The principal that owns the file.
+    pub const fn owner(&self) -> Uuid {
+        self.0.owner
+    }
+
+    /// The `ACCESS_*` modes the current thread is granted on the file.
+    pub const fn access(&self) -> u32 {
+        self.0.access
+    }
+
+    /// The last modification time, relative to the Unix Epoch.
+    pub const fn modified(&self) -> Duration {
+        self.0.modified
+    }
+
+    /// Uniquely identifies the underlying object, regardless of the path used to open it.
+    pub const fn id(&self) -> (u64, u64) {
+        (self.0.volume_id, self.0.object_id)
+    }
+}
+
+pub fn metadata(path: &str) -> Result<Metadata> {
+    OpenOptions::new().open(path)?.metadata()
+}
+
+/// Like [`metadata`], but does not follow `path` if it is a link.
+pub fn symlink_metadata(path: &str) -> Result<Metadata> {
+    OpenOptions::new().no_follow(true).open(path)?.metadata()
+}
+
+/// Returns an iterator over the entries of the directory at `path`.
+pub fn read_dir(path: &str) -> Result<ReadDir> {
+    OpenOptions::new()
+        .read(true)
+        .directory(true)
+        .open(path)
+        .map(ReadDir::new)
+}
+
+/// An iterator over the entries of a directory, created by [`read_dir`].
+pub struct ReadDir {
+    dir: Rc<File>,
+    done: bool,
+}
+
+impl ReadDir {
+    /// Iterates over the entries of `dir`, which must have been opened as a directory.
+    pub fn new(dir: File) -> Self {
+        Self {
+            dir: Rc::new(dir),
+            done: false,
+        }
+    }
+}
+
+impl Iterator for ReadDir {
+    type Item = Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut name = String::with_capacity(64);
+        loop {
+            let mut record = DirectoryRecord {
+                name: KStrPtr {
+                    str_ptr: name.as_mut_ptr(),
+                    len: name.capacity(),
+                },
+                ..bytemuck::Zeroable::zeroed()
+            };
+
+            let res = unsafe { ReadDirectory(self.dir.as_raw_handle(), &mut record) };
+
+            if res == FINISHED_ENUMERATE {
+                self.done = true;
+                return None;
+            } else if res == INSUFFICIENT_LENGTH {
+                // The entry is left in place, so we can retry it with a large enough buffer
+                name.reserve(record.name.len);
+                continue;
+            } else if res < 0 {
+                self.done = true;
+                return Some(Err(Error::from_raw_os_error(res)));
+            }
+
+            unsafe {
+                name.as_mut_vec().set_len(record.name.len);
+            }
+
+            return Some(Ok(DirEntry {
+                dir: self.dir.clone(),
+                name,
+                file_type: FileType(record.file_type),
+            }));
+        }
+    }
+}
+
+/// An entry in a directory, returned by [`ReadDir`].
+pub struct DirEntry {
+    dir: Rc<File>,
+    name: String,
+    file_type: FileType,
+}
+
+impl DirEntry {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn into_name(self) -> String {
+        self.name
+    }
+
+    /// The type of the entry, which is not followed if it is a link.
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// Reads the metadata of the entry, without following it if it is a link.
+    pub fn metadata(&self) -> Result<Metadata> {
+        self.open(OpenOptions::new().no_follow(true))?.metadata()
+    }
+
+    /// Opens the entry relative to its directory.
+    pub fn open(&self, opts: &OpenOptions) -> Result<File> {
+        opts.open_at(self.dir.as_raw_handle(), &self.name)
+    }
+}
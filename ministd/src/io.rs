@@ -1,18 +1,34 @@
-use alloc::{string::String, vec::Vec};
+use core::cell::{Cell, RefCell, RefMut};
+
+use alloc::{boxed::Box, string::String, vec::Vec};
 use genio::{Read, Write, bufio::BufRead};
 use lilium_sys::sys::{
     handle::HandlePtr,
     io::{__HANDLE_IO_STDERR, __HANDLE_IO_STDIN, __HANDLE_IO_STDOUT, IOHandle, IORead, IOWrite},
 };
 
+mod buffered;
 mod error;
+mod pipe;
+mod terminal;
 
+pub use buffered::{BufWriter, LineWriter};
 pub use error::{Error, ErrorKind};
+pub use pipe::{PipeReader, PipeWriter, pipe};
+pub use terminal::RawMode;
+
+use crate::helpers::AssertThreadSafe;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
 pub struct Stdio(HandlePtr<IOHandle>);
 
+impl Stdio {
+    pub const fn as_raw_handle(&self) -> HandlePtr<IOHandle> {
+        self.0
+    }
+}
+
 impl core::fmt::Write for Stdio {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         if unsafe { IOWrite(self.0, s.as_ptr().cast(), s.len() as _) } < 0 {
@@ -23,19 +39,103 @@ impl core::fmt::Write for Stdio {
     }
 }
 
+// SAFETY:
+// Like the globals in `start`, these are only used from the main thread
+static STDIN: AssertThreadSafe<Cell<HandlePtr<IOHandle>>> =
+    unsafe { AssertThreadSafe::new_unchecked(Cell::new(__HANDLE_IO_STDIN)) };
+
+static STDERR: AssertThreadSafe<Cell<HandlePtr<IOHandle>>> =
+    unsafe { AssertThreadSafe::new_unchecked(Cell::new(__HANDLE_IO_STDERR)) };
+
 #[inline(always)]
 pub fn stdin() -> Stdio {
-    Stdio(__HANDLE_IO_STDIN)
+    Stdio(STDIN.get())
+}
+
+// SAFETY:
+// Like the globals in `start`, this is only used from the main thread
+pub(crate) static STDOUT: AssertThreadSafe<RefCell<LineWriter<Stdio>>> = unsafe {
+    AssertThreadSafe::new_unchecked(RefCell::new(LineWriter::new(Stdio(__HANDLE_IO_STDOUT))))
+};
+
+/// A handle to the shared, line-buffered standard output.
+///
+/// Output is written when a newline is written, on [`Write::flush`],
+/// and when the program exits via `main` or [`exit`][crate::exit].
+pub struct Stdout(());
+
+impl Stdout {
+    /// Borrows the shared writer. Panics if it is already borrowed.
+    pub fn lock(&self) -> RefMut<'static, LineWriter<Stdio>> {
+        STDOUT.borrow_mut()
+    }
+
+    pub fn as_raw_handle(&self) -> HandlePtr<IOHandle> {
+        self.lock().get_ref().0
+    }
+}
+
+impl core::fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        core::fmt::Write::write_str(&mut *self.lock(), s)
+    }
+}
+
+impl Write for Stdout {
+    type WriteError = Error;
+    type FlushError = Error;
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.lock().flush()
+    }
+
+    fn size_hint(&mut self, _: usize) {}
 }
 
 #[inline(always)]
-pub fn stdout() -> Stdio {
-    Stdio(__HANDLE_IO_STDOUT)
+pub fn stdout() -> Stdout {
+    Stdout(())
 }
 
 #[inline(always)]
 pub fn stderr() -> Stdio {
-    Stdio(__HANDLE_IO_STDERR)
+    Stdio(STDERR.get())
+}
+
+/// Makes [`stdin`] return `hdl`, and returns the handle it returned before.
+///
+/// Processes that inherit stdin receive `hdl` as well.
+///
+/// # Safety
+/// `hdl` must stay open until it is replaced.
+pub unsafe fn set_stdin(hdl: HandlePtr<IOHandle>) -> HandlePtr<IOHandle> {
+    STDIN.replace(hdl)
+}
+
+/// Makes [`stdout`] write to `hdl`, after writing out its buffer, and returns the handle it wrote to before.
+///
+/// Processes that inherit stdout receive `hdl` as well. Errors writing out the buffer are ignored.
+///
+/// # Safety
+/// `hdl` must stay open until it is replaced.
+pub unsafe fn set_stdout(hdl: HandlePtr<IOHandle>) -> HandlePtr<IOHandle> {
+    let mut stdout = STDOUT.borrow_mut();
+    let _ = stdout.flush();
+    core::mem::replace(&mut stdout.get_mut().0, hdl)
+}
+
+/// Makes [`stderr`] return `hdl`, and returns the handle it returned before.
+///
+/// Processes that inherit stderr receive `hdl` as well.
+///
+/// # Safety
+/// `hdl` must stay open until it is replaced.
+pub unsafe fn set_stderr(hdl: HandlePtr<IOHandle>) -> HandlePtr<IOHandle> {
+    STDERR.replace(hdl)
 }
 
 #[macro_export]
@@ -115,23 +215,87 @@ impl Write for Stdio {
     }
 }
 
+const INLINE_BUF_SIZE: usize = 64;
+const DEFAULT_BUF_SIZE: usize = 4096;
+
+enum Buffer {
+    Inline([u8; INLINE_BUF_SIZE]),
+    Heap(Box<[u8]>),
+}
+
+impl Buffer {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Buffer::Inline(buf) => buf,
+            Buffer::Heap(buf) => buf,
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        match self {
+            Buffer::Inline(buf) => buf,
+            Buffer::Heap(buf) => buf,
+        }
+    }
+}
+
 pub struct BufReader<R> {
     underlying: R,
     buf_pos: usize,
     buf_len: usize,
-    buf: [u8; 64],
+    buf: Buffer,
 }
 
 impl<R> BufReader<R> {
-    pub const fn new(inner: R) -> Self {
+    /// Creates a `BufReader` with a heap-allocated buffer of a default size.
+    pub fn new(inner: R) -> Self {
+        Self::with_capacity(DEFAULT_BUF_SIZE, inner)
+    }
+
+    /// Creates a `BufReader` with a heap-allocated buffer of `capacity` bytes.
+    pub fn with_capacity(capacity: usize, inner: R) -> Self {
+        Self {
+            underlying: inner,
+            buf_pos: 0,
+            buf_len: 0,
+            buf: Buffer::Heap(alloc::vec![0; capacity].into_boxed_slice()),
+        }
+    }
+
+    /// Creates a `BufReader` with a small buffer stored inline, which does not allocate.
+    pub const fn new_small(inner: R) -> Self {
         Self {
             underlying: inner,
             buf_pos: 0,
             buf_len: 0,
-            buf: [0; 64],
+            buf: Buffer::Inline([0; INLINE_BUF_SIZE]),
         }
     }
 
+    pub fn capacity(&self) -> usize {
+        self.buf.as_slice().len()
+    }
+
+    /// The bytes that have been read from the underlying reader but not yet consumed.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf.as_slice()[self.buf_pos..self.buf_len]
+    }
+
+    /// Drops the contents of the buffer, so the next read goes to the underlying reader.
+    pub fn discard_buffer(&mut self) {
+        self.buf_pos = 0;
+        self.buf_len = 0;
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.underlying
+    }
+
+    /// Note that reading from the returned reader directly skips over any data that is still buffered.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.underlying
+    }
+
     pub fn into_inner(self) -> R {
         self.underlying
     }
@@ -140,21 +304,27 @@ impl<R> BufReader<R> {
 impl<R: Read> BufRead for BufReader<R> {
     fn fill_buf(&mut self) -> core::result::Result<&[u8], Self::ReadError> {
         if self.buf_pos >= self.buf_len {
-            self.buf_len = self.underlying.read(&mut self.buf)?;
+            self.buf_len = self.underlying.read(self.buf.as_mut_slice())?;
             self.buf_pos = 0;
         }
 
-        Ok(&self.buf[self.buf_pos..self.buf_len])
+        Ok(self.buffer())
     }
 
     fn consume(&mut self, amount: usize) {
-        self.buf_pos += amount;
+        self.buf_pos = (self.buf_pos + amount).min(self.buf_len);
     }
 }
 
 impl<R: Read> Read for BufReader<R> {
     type ReadError = R::ReadError;
     fn read(&mut self, buf: &mut [u8]) -> core::result::Result<usize, Self::ReadError> {
+        // Large reads into an empty buffer would only be copied straight out again
+        if self.buf_pos >= self.buf_len && buf.len() >= self.capacity() {
+            self.discard_buffer();
+            return self.underlying.read(buf);
+        }
+
         let inner = self.fill_buf()?;
         let len = inner.len().min(buf.len());
         buf[..len].copy_from_slice(&inner[..len]);
@@ -163,6 +333,57 @@ impl<R: Read> Read for BufReader<R> {
     }
 }
 
+impl<R: Seek> Seek for BufReader<R> {
+    /// Seeks the underlying reader, discarding the buffer.
+    ///
+    /// [`SeekFrom::Current`] is relative to the position of the `BufReader`, not the underlying reader.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let pos = match pos {
+            SeekFrom::Current(n) => {
+                let remaining = (self.buf_len - self.buf_pos) as i64;
+                self.underlying.seek(SeekFrom::Current(n - remaining))?
+            }
+            pos => self.underlying.seek(pos)?,
+        };
+        self.discard_buffer();
+        Ok(pos)
+    }
+}
+
+/// A position to seek to, for [`Seek::seek`].
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum SeekFrom {
+    /// An offset from the start of the stream.
+    Start(u64),
+    /// An offset from the end of the stream.
+    End(i64),
+    /// An offset from the current position.
+    Current(i64),
+}
+
+/// A stream with a cursor that can be moved.
+///
+/// Streams that do not support seeking fail with [`ErrorKind::NotSeekable`].
+pub trait Seek {
+    /// Moves the cursor to `pos`, returning the new position relative to the start of the stream.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64>;
+
+    fn rewind(&mut self) -> Result<()> {
+        self.seek(SeekFrom::Start(0)).map(|_| ())
+    }
+
+    /// Returns the current position relative to the start of the stream.
+    fn stream_position(&mut self) -> Result<u64> {
+        self.seek(SeekFrom::Current(0))
+    }
+}
+
+impl<S: Seek + ?Sized> Seek for &mut S {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        (**self).seek(pos)
+    }
+}
+
 mod ex;
 
-pub use ex::{BufReadEx, ReadToStringError};
+pub use ex::{BufReadEx, Lines, ReadEx, ReadToStringError, Split};
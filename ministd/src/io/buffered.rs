@@ -0,0 +1,255 @@
+use core::mem::ManuallyDrop;
+
+use alloc::vec::Vec;
+use genio::Write;
+use memchr::memrchr;
+
+use super::{Error, ErrorKind, Seek, SeekFrom};
+
+const DEFAULT_BUF_SIZE: usize = 1024;
+
+/// Wraps a [`Write`]r and buffers its output, so that many small writes turn into few large ones.
+///
+/// The buffer is written out when it fills up, on [`Write::flush`], and when the `BufWriter` is dropped.
+/// Errors that occur while writing on drop are ignored.
+pub struct BufWriter<W: Write> {
+    buf: Vec<u8>,
+    capacity: usize,
+    inner: W,
+}
+
+impl<W: Write> BufWriter<W> {
+    pub const fn new(inner: W) -> Self {
+        Self::with_capacity(DEFAULT_BUF_SIZE, inner)
+    }
+
+    /// Creates a `BufWriter` that buffers up to `capacity` bytes.
+    ///
+    /// The buffer is not allocated until the first write.
+    pub const fn with_capacity(capacity: usize, inner: W) -> Self {
+        Self {
+            buf: Vec::new(),
+            capacity,
+            inner,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Note that writing to the returned writer directly bypasses any data that is still buffered.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// The bytes that have been written but not yet passed to the underlying writer.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Passes the buffer to the underlying writer.
+    ///
+    /// If the underlying writer stops accepting bytes, the remainder is kept in the buffer.
+    fn flush_buf(&mut self) -> Result<(), W::WriteError> {
+        let mut written = 0;
+        let mut ret = Ok(());
+        while written < self.buf.len() {
+            match self.inner.write(&self.buf[written..]) {
+                Ok(0) => break,
+                Ok(n) => written += n,
+                Err(e) => {
+                    ret = Err(e);
+                    break;
+                }
+            }
+        }
+        self.buf.drain(..written);
+        ret
+    }
+}
+
+impl<W: Write> BufWriter<W>
+where
+    W::WriteError: From<Error>,
+{
+    /// Writes out the buffer and returns the underlying writer.
+    pub fn into_inner(mut self) -> Result<W, W::WriteError> {
+        self.flush_buf_all()?;
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never used again, and its `Drop` impl is suppressed.
+        unsafe {
+            core::ptr::drop_in_place(&mut this.buf);
+            Ok(core::ptr::read(&this.inner))
+        }
+    }
+
+    /// Passes the whole buffer to the underlying writer, failing if it stops accepting bytes.
+    fn flush_buf_all(&mut self) -> Result<(), W::WriteError> {
+        self.flush_buf()?;
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(write_zero().into())
+        }
+    }
+}
+
+fn write_zero() -> Error {
+    Error::new_with_message(ErrorKind::WriteZero, "Failed to write the buffered data")
+}
+
+impl<W: Write> Write for BufWriter<W>
+where
+    W::FlushError: Into<W::WriteError>,
+    W::WriteError: From<Error>,
+{
+    type WriteError = W::WriteError;
+    type FlushError = W::WriteError;
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::WriteError> {
+        if self.buf.len() + buf.len() > self.capacity {
+            self.flush_buf_all()?;
+        }
+
+        if buf.len() >= self.capacity {
+            match self.inner.write(buf)? {
+                0 if !buf.is_empty() => Err(write_zero().into()),
+                n => Ok(n),
+            }
+        } else {
+            if self.buf.capacity() == 0 {
+                self.buf.reserve_exact(self.capacity);
+            }
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushError> {
+        self.flush_buf_all()?;
+        self.inner.flush().map_err(Into::into)
+    }
+
+    fn size_hint(&mut self, bytes: usize) {
+        self.inner.size_hint(bytes)
+    }
+
+    fn uses_size_hint(&self) -> bool {
+        self.inner.uses_size_hint()
+    }
+}
+
+impl<W: Write> core::fmt::Write for BufWriter<W>
+where
+    W::FlushError: Into<W::WriteError>,
+    W::WriteError: From<Error>,
+{
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+impl<W: Write<WriteError = Error> + Seek> Seek for BufWriter<W> {
+    /// Writes out the buffer, then seeks the underlying writer.
+    fn seek(&mut self, pos: SeekFrom) -> super::Result<u64> {
+        self.flush_buf_all()?;
+        self.inner.seek(pos)
+    }
+}
+
+impl<W: Write> Drop for BufWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush_buf();
+    }
+}
+
+/// A [`BufWriter`] that also writes out its buffer whenever a newline is written.
+///
+/// Each complete line is passed to the underlying writer in a single call where possible.
+pub struct LineWriter<W: Write> {
+    inner: BufWriter<W>,
+}
+
+impl<W: Write> LineWriter<W> {
+    pub const fn new(inner: W) -> Self {
+        Self {
+            inner: BufWriter::new(inner),
+        }
+    }
+
+    pub const fn with_capacity(capacity: usize, inner: W) -> Self {
+        Self {
+            inner: BufWriter::with_capacity(capacity, inner),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.get_mut()
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        self.inner.buffer()
+    }
+}
+
+impl<W: Write> LineWriter<W>
+where
+    W::WriteError: From<Error>,
+{
+    pub fn into_inner(self) -> Result<W, W::WriteError> {
+        self.inner.into_inner()
+    }
+}
+
+impl<W: Write> Write for LineWriter<W>
+where
+    W::FlushError: Into<W::WriteError>,
+    W::WriteError: From<Error>,
+{
+    type WriteError = W::WriteError;
+    type FlushError = W::WriteError;
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::WriteError> {
+        match memrchr(b'\n', buf) {
+            Some(n) => {
+                let (lines, rest) = buf.split_at(n + 1);
+                self.inner.write_all(lines)?;
+                self.inner.flush_buf_all()?;
+                let m = self.inner.write(rest)?;
+                Ok(lines.len() + m)
+            }
+            None => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushError> {
+        self.inner.flush()
+    }
+
+    fn size_hint(&mut self, bytes: usize) {
+        self.inner.size_hint(bytes)
+    }
+
+    fn uses_size_hint(&self) -> bool {
+        self.inner.uses_size_hint()
+    }
+}
+
+impl<W: Write> core::fmt::Write for LineWriter<W>
+where
+    W::FlushError: Into<W::WriteError>,
+    W::WriteError: From<Error>,
+{
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
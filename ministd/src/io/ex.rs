@@ -1,15 +1,50 @@
 use alloc::{string::String, vec::Vec};
-use genio::bufio::BufRead;
+use genio::{Read, bufio::BufRead};
 use memchr::memchr;
 
+use super::{Error, ErrorKind};
+
+#[derive(Debug)]
 pub enum ReadToStringError<R> {
     Read(R),
     InvalidUtf8,
 }
 
+impl From<ReadToStringError<Error>> for Error {
+    fn from(value: ReadToStringError<Error>) -> Self {
+        match value {
+            ReadToStringError::Read(e) => e,
+            ReadToStringError::InvalidUtf8 => {
+                Error::new_with_message(ErrorKind::InvalidData, "Invalid UTF-8 Text")
+            }
+        }
+    }
+}
+
 pub trait BufReadEx: BufRead {
+    /// Appends bytes to `buf` up to and including the delimiter `b`, or until EOF.
+    ///
+    /// Returns the number of bytes appended, which is `0` only at EOF.
     fn read_until(&mut self, buf: &mut Vec<u8>, b: u8) -> Result<usize, Self::ReadError>;
+    /// Like [`BufReadEx::read_until`], but discards the bytes instead of storing them.
+    fn skip_until(&mut self, b: u8) -> Result<usize, Self::ReadError>;
     fn read_line(&mut self, st: &mut String) -> Result<usize, ReadToStringError<Self::ReadError>>;
+
+    /// Returns an iterator over the chunks of the input separated by `b`, with the delimiter removed.
+    fn split(self, b: u8) -> Split<Self>
+    where
+        Self: Sized,
+    {
+        Split { reader: self, b }
+    }
+
+    /// Returns an iterator over the lines of the input, with the trailing `\n` or `\r\n` removed.
+    fn lines(self) -> Lines<Self>
+    where
+        Self: Sized,
+    {
+        Lines { reader: self }
+    }
 }
 
 impl<R: BufRead> BufReadEx for R {
@@ -25,7 +60,7 @@ impl<R: BufRead> BufReadEx for R {
             match memchr(b, inner_buf) {
                 Some(n) => {
                     let n = n + 1;
-                    total_len += n + 1;
+                    total_len += n;
                     buf.extend_from_slice(&inner_buf[..n]);
                     self.consume(n);
                     break Ok(total_len);
@@ -39,11 +74,120 @@ impl<R: BufRead> BufReadEx for R {
             }
         }
     }
+    fn skip_until(&mut self, b: u8) -> Result<usize, Self::ReadError> {
+        let mut total_len = 0;
+        loop {
+            let inner_buf = self.fill_buf()?;
+
+            if inner_buf.len() == 0 {
+                return Ok(total_len);
+            }
+
+            match memchr(b, inner_buf) {
+                Some(n) => {
+                    let n = n + 1;
+                    total_len += n;
+                    self.consume(n);
+                    break Ok(total_len);
+                }
+                None => {
+                    let n = inner_buf.len();
+                    total_len += n;
+                    self.consume(n);
+                }
+            }
+        }
+    }
     fn read_line(&mut self, st: &mut String) -> Result<usize, ReadToStringError<Self::ReadError>> {
         unsafe { append_to_string(st, |buf| self.read_until(buf, b'\n')) }
     }
 }
 
+/// An iterator over the chunks of a [`BufRead`], created by [`BufReadEx::split`].
+pub struct Split<R> {
+    reader: R,
+    b: u8,
+}
+
+impl<R: BufRead> Iterator for Split<R> {
+    type Item = Result<Vec<u8>, R::ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = Vec::new();
+        match self.reader.read_until(&mut buf, self.b) {
+            Ok(0) => None,
+            Ok(_) => {
+                if buf.last() == Some(&self.b) {
+                    buf.pop();
+                }
+                Some(Ok(buf))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// An iterator over the lines of a [`BufRead`], created by [`BufReadEx::lines`].
+pub struct Lines<R> {
+    reader: R,
+}
+
+impl<R: BufRead> Iterator for Lines<R> {
+    type Item = Result<String, ReadToStringError<R::ReadError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Some(Ok(line))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Extensions to [`Read`] for reading until EOF.
+///
+/// Note that [`Read`] has its own `read_to_end`, so calls may need to be qualified when both traits are in scope.
+pub trait ReadEx: Read {
+    /// Appends all bytes until EOF to `buf`, returning the number of bytes appended.
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, Self::ReadError>;
+    /// Appends all bytes until EOF to `st`, which must be valid UTF-8.
+    ///
+    /// If the input is not valid UTF-8, `st` is left unchanged.
+    fn read_to_string(
+        &mut self,
+        st: &mut String,
+    ) -> Result<usize, ReadToStringError<Self::ReadError>>;
+}
+
+impl<R: Read + ?Sized> ReadEx for R {
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, Self::ReadError> {
+        let start = buf.len();
+        // Reading into a buffer of our own avoids zeroing the spare capacity of `buf` on every read
+        let mut chunk = [0; 1024];
+        loop {
+            match self.read(&mut chunk)? {
+                0 => return Ok(buf.len() - start),
+                n => buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+    fn read_to_string(
+        &mut self,
+        st: &mut String,
+    ) -> Result<usize, ReadToStringError<Self::ReadError>> {
+        unsafe { append_to_string(st, |buf| ReadEx::read_to_end(self, buf)) }
+    }
+}
+
 // Taken from std::io impl
 
 struct Guard<'a> {
@@ -0,0 +1,91 @@
+use genio::{Read, Write};
+use lilium_sys::sys::{
+    handle::{CloseHandle, HandlePtr},
+    io::{CreatePipe, IOHandle, IORead, IOWrite},
+};
+
+use super::{Error, Result};
+
+/// Creates an anonymous pipe, returning its read and write ends.
+///
+/// Reads from the [`PipeReader`] return EOF once every copy of the [`PipeWriter`] is closed.
+pub fn pipe() -> Result<(PipeReader, PipeWriter)> {
+    let mut read = HandlePtr::null();
+    let mut write = HandlePtr::null();
+    let res = unsafe { CreatePipe(&mut read, &mut write) };
+
+    if res < 0 {
+        Err(Error::from_raw_os_error(res))
+    } else {
+        Ok((PipeReader(read), PipeWriter(write)))
+    }
+}
+
+/// The read end of a pipe, which is closed when dropped.
+pub struct PipeReader(HandlePtr<IOHandle>);
+
+/// The write end of a pipe, which is closed when dropped.
+pub struct PipeWriter(HandlePtr<IOHandle>);
+
+impl PipeReader {
+    pub const fn as_raw_handle(&self) -> HandlePtr<IOHandle> {
+        self.0
+    }
+}
+
+impl PipeWriter {
+    pub const fn as_raw_handle(&self) -> HandlePtr<IOHandle> {
+        self.0
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0.cast());
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0.cast());
+        }
+    }
+}
+
+impl Read for PipeReader {
+    type ReadError = Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = unsafe { IORead(self.0, buf.as_mut_ptr().cast(), buf.len() as _) };
+
+        if n < 0 {
+            Err(Error::from_raw_os_error(n))
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+impl Write for PipeWriter {
+    type WriteError = Error;
+    type FlushError = Error;
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = unsafe { IOWrite(self.0, buf.as_ptr().cast(), buf.len() as _) };
+
+        if n < 0 {
+            Err(Error::from_raw_os_error(n))
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn size_hint(&mut self, _: usize) {}
+}
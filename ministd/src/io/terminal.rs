@@ -0,0 +1,47 @@
+use lilium_sys::sys::{
+    handle::HandlePtr,
+    io::{GetTerminalMode, IOHandle, SetTerminalMode, TERMINAL_MODE_ECHO, TERMINAL_MODE_LINE},
+};
+
+use super::{Error, Result, Stdio};
+
+impl Stdio {
+    /// Whether the handle refers to a terminal, rather than a file or pipe.
+    pub fn is_terminal(&self) -> bool {
+        let mut mode = 0;
+        unsafe { GetTerminalMode(self.0, &mut mode) >= 0 }
+    }
+
+    /// Switches the terminal to raw mode, where reads return each byte as it is typed, and input is not echoed.
+    ///
+    /// Fails if the handle is not a terminal. The previous mode is restored when the returned [`RawMode`] is dropped.
+    pub fn enable_raw_mode(&self) -> Result<RawMode> {
+        let mut prev = 0;
+        let res = unsafe { GetTerminalMode(self.0, &mut prev) };
+        if res < 0 {
+            return Err(Error::from_raw_os_error(res));
+        }
+
+        let res =
+            unsafe { SetTerminalMode(self.0, prev & !(TERMINAL_MODE_ECHO | TERMINAL_MODE_LINE)) };
+        if res < 0 {
+            Err(Error::from_raw_os_error(res))
+        } else {
+            Ok(RawMode { hdl: self.0, prev })
+        }
+    }
+}
+
+/// A terminal in raw mode, which is switched back to its previous mode when dropped.
+pub struct RawMode {
+    hdl: HandlePtr<IOHandle>,
+    prev: u32,
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            SetTerminalMode(self.hdl, self.prev);
+        }
+    }
+}
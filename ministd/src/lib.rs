@@ -12,14 +12,21 @@ use crate::io::stderr;
 
 extern crate alloc;
 
+pub mod env;
+pub mod fs;
 pub mod helpers;
 pub mod io;
+pub mod process;
 pub mod start;
 pub mod system;
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     use core::fmt::Write;
+    // The panic may have happened while stdout was borrowed, in which case its buffer is lost
+    if let Ok(mut stdout) = io::STDOUT.try_borrow_mut() {
+        let _ = genio::Write::flush(&mut *stdout);
+    }
     if let Some(loc) = info.location() {
         let _ = writeln!(stderr(), "Panicked at {loc}: {}", info.message());
     } else {
@@ -36,5 +43,12 @@ fn panic(info: &PanicInfo) -> ! {
 }
 
 unsafe extern "C" {
-    pub safe fn exit(x: i32) -> !;
+    #[link_name = "exit"]
+    safe fn exit_process(x: i32) -> !;
+}
+
+/// Flushes [`io::stdout`] and exits the process with status `x`.
+pub fn exit(x: i32) -> ! {
+    let _ = genio::Write::flush(&mut io::stdout());
+    exit_process(x)
 }
@@ -0,0 +1,623 @@
+use alloc::{borrow::Cow, format, string::String, vec::Vec};
+use lilium_sys::sys::{
+    error::{DOES_NOT_EXIST, WOULD_BLOCK},
+    except::{ExceptionStatusInfo, UnmanagedException},
+    fs::FileHandle,
+    handle::HandlePtr,
+    io::IOHandle,
+    kstr::{KCSlice, KStrCPtr},
+    option::ExtendedOptionHead,
+    process::{
+        CREATE_PROCESS_OPTION_ARGS, CREATE_PROCESS_OPTION_ENV, CREATE_PROCESS_OPTION_INIT_HANDLES,
+        CreateProcess, CreateProcessOption, CreateProcessOptionArgs, CreateProcessOptionEnv,
+        CreateProcessOptionInitHandles, DetachProcess, GetProcessId, INIT_HANDLE_STDERR,
+        INIT_HANDLE_STDIN, INIT_HANDLE_STDOUT, InitHandle, JoinProcess, ProcessHandle,
+        TryJoinProcess,
+    },
+    thread::{JOIN_STATUS_EXCEPTION, JOIN_STATUS_EXITED, JOIN_STATUS_KILLED, JoinStatus},
+};
+
+use crate::{
+    eprintln,
+    fs::{File, OpenOptions},
+    io::{self, Error, PipeReader, PipeWriter, ReadEx},
+    start::Termination,
+};
+
+/// The id of the current process.
+pub fn id() -> u64 {
+    process_id(HandlePtr::null())
+}
+
+fn process_id(hdl: HandlePtr<ProcessHandle>) -> u64 {
+    let mut id = 0;
+    let res = unsafe { GetProcessId(hdl, &mut id) };
+    // Only fails for invalid handles
+    debug_assert!(res >= 0);
+    id
+}
+
+/// A builder for spawning a child process.
+///
+/// By default, the child inherits the environment and the standard I/O handles of the current process.
+pub struct Command {
+    program: String,
+    args: Vec<String>,
+    env: Vec<(String, Option<String>)>,
+    env_clear: bool,
+    current_dir: Option<String>,
+    search_dirs: Option<Vec<HandlePtr<FileHandle>>>,
+    stdin: Option<Stdio>,
+    stdout: Option<Stdio>,
+    stderr: Option<Stdio>,
+}
+
+impl Command {
+    /// Creates a `Command` that runs `program`.
+    ///
+    /// If `program` does not contain a `/`, it is searched for in the directories in `PATH`.
+    pub fn new(program: &str) -> Self {
+        Self {
+            program: String::from(program),
+            args: Vec::new(),
+            env: Vec::new(),
+            env_clear: false,
+            current_dir: None,
+            search_dirs: None,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+        }
+    }
+
+    pub fn get_program(&self) -> &str {
+        &self.program
+    }
+
+    pub fn arg(&mut self, arg: &str) -> &mut Self {
+        self.args.push(String::from(arg));
+        self
+    }
+
+    pub fn args<I: IntoIterator<Item = S>, S: AsRef<str>>(&mut self, args: I) -> &mut Self {
+        self.args
+            .extend(args.into_iter().map(|arg| String::from(arg.as_ref())));
+        self
+    }
+
+    /// Sets the environment variable `key` in the child.
+    pub fn env(&mut self, key: &str, val: &str) -> &mut Self {
+        self.set_env(key, Some(val));
+        self
+    }
+
+    pub fn envs<I: IntoIterator<Item = (K, V)>, K: AsRef<str>, V: AsRef<str>>(
+        &mut self,
+        vars: I,
+    ) -> &mut Self {
+        for (key, val) in vars {
+            self.set_env(key.as_ref(), Some(val.as_ref()));
+        }
+        self
+    }
+
+    /// Removes the environment variable `key` from the child.
+    pub fn env_remove(&mut self, key: &str) -> &mut Self {
+        self.set_env(key, None);
+        self
+    }
+
+    /// Stops the child from inheriting the environment of the current process.
+    ///
+    /// Variables set with [`Command::env`] are still passed to the child.
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env_clear = true;
+        self.env.clear();
+        self
+    }
+
+    fn set_env(&mut self, key: &str, val: Option<&str>) {
+        let val = val.map(String::from);
+        match self.env.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = val,
+            None => self.env.push((String::from(key), val)),
+        }
+    }
+
+    /// Sets the working directory of the child, rather than the [current directory][crate::env::current_dir].
+    ///
+    /// A `program` containing a `/` is resolved relative to `dir`, and the child receives `dir` as `PWD`.
+    pub fn current_dir(&mut self, dir: &str) -> &mut Self {
+        self.current_dir = Some(String::from(dir));
+        self
+    }
+
+    /// Searches for the program in `dirs`, rather than in the directories listed in `PATH`.
+    ///
+    /// The handles are borrowed, and must remain open until the child is spawned.
+    pub fn search_dirs<I: IntoIterator<Item = HandlePtr<FileHandle>>>(
+        &mut self,
+        dirs: I,
+    ) -> &mut Self {
+        self.search_dirs = Some(dirs.into_iter().collect());
+        self
+    }
+
+    pub fn stdin<S: Into<Stdio>>(&mut self, stdin: S) -> &mut Self {
+        self.stdin = Some(stdin.into());
+        self
+    }
+
+    pub fn stdout<S: Into<Stdio>>(&mut self, stdout: S) -> &mut Self {
+        self.stdout = Some(stdout.into());
+        self
+    }
+
+    pub fn stderr<S: Into<Stdio>>(&mut self, stderr: S) -> &mut Self {
+        self.stderr = Some(stderr.into());
+        self
+    }
+
+    /// Spawns the child without waiting for it.
+    ///
+    /// Handles passed with [`Command::stdin`], [`Command::stdout`] and [`Command::stderr`] are closed in the
+    /// current process once the child has been spawned.
+    pub fn spawn(&mut self) -> io::Result<Child> {
+        // `joined` stays set until there's a process to detach
+        let mut child = Child {
+            hdl: HandlePtr::null(),
+            joined: true,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+        };
+        // The ends of any pipes and files given to the child, which must stay open until it's spawned
+        let mut child_ends = Vec::new();
+
+        let stdin = match self.stdin.take().unwrap_or(Stdio::inherit()).0 {
+            StdioKind::Inherit => io::stdin().as_raw_handle(),
+            StdioKind::Piped => {
+                let (read, write) = io::pipe()?;
+                child.stdin = Some(write);
+                child_ends.push(StdioKind::PipeReader(read));
+                child_ends.last().unwrap().raw_handle()
+            }
+            kind => {
+                child_ends.push(kind);
+                child_ends.last().unwrap().raw_handle()
+            }
+        };
+
+        let stdout = match self.stdout.take().unwrap_or(Stdio::inherit()).0 {
+            StdioKind::Inherit => io::stdout().as_raw_handle(),
+            StdioKind::Piped => {
+                let (read, write) = io::pipe()?;
+                child.stdout = Some(read);
+                child_ends.push(StdioKind::PipeWriter(write));
+                child_ends.last().unwrap().raw_handle()
+            }
+            kind => {
+                child_ends.push(kind);
+                child_ends.last().unwrap().raw_handle()
+            }
+        };
+
+        let stderr = match self.stderr.take().unwrap_or(Stdio::inherit()).0 {
+            StdioKind::Inherit => io::stderr().as_raw_handle(),
+            StdioKind::Piped => {
+                let (read, write) = io::pipe()?;
+                child.stderr = Some(read);
+                child_ends.push(StdioKind::PipeWriter(write));
+                child_ends.last().unwrap().raw_handle()
+            }
+            kind => {
+                child_ends.push(kind);
+                child_ends.last().unwrap().raw_handle()
+            }
+        };
+
+        let init_handles = [
+            InitHandle {
+                slot: INIT_HANDLE_STDIN,
+                hdl: stdin.cast(),
+            },
+            InitHandle {
+                slot: INIT_HANDLE_STDOUT,
+                hdl: stdout.cast(),
+            },
+            InitHandle {
+                slot: INIT_HANDLE_STDERR,
+                hdl: stderr.cast(),
+            },
+        ];
+
+        let args = core::iter::once(&self.program)
+            .chain(&self.args)
+            .map(|arg| KStrCPtr::from_str(arg))
+            .collect::<Vec<_>>();
+
+        let env = self.build_env();
+        let env = env
+            .iter()
+            .map(|var| KStrCPtr::from_str(var))
+            .collect::<Vec<_>>();
+
+        let opts = [
+            CreateProcessOption {
+                args: CreateProcessOptionArgs {
+                    header: ExtendedOptionHead {
+                        ty: CREATE_PROCESS_OPTION_ARGS,
+                        ..ExtendedOptionHead::ZERO
+                    },
+                    arguments: KCSlice::from_slice(&args),
+                },
+            },
+            CreateProcessOption {
+                env: CreateProcessOptionEnv {
+                    header: ExtendedOptionHead {
+                        ty: CREATE_PROCESS_OPTION_ENV,
+                        ..ExtendedOptionHead::ZERO
+                    },
+                    env: KCSlice::from_slice(&env),
+                },
+            },
+            CreateProcessOption {
+                init_handles: CreateProcessOptionInitHandles {
+                    header: ExtendedOptionHead {
+                        ty: CREATE_PROCESS_OPTION_INIT_HANDLES,
+                        ..ExtendedOptionHead::ZERO
+                    },
+                    init_handles: KCSlice::from_slice(&init_handles),
+                },
+            },
+        ];
+
+        // Anything still buffered belongs before the child's output
+        let _ = genio::Write::flush(&mut io::stdout());
+
+        let path = KStrCPtr::from_str(&self.program);
+        let res = if self.program.contains('/') {
+            let base = match &self.current_dir {
+                Some(dir) => Some(OpenOptions::new().read(true).directory(true).open(dir)?),
+                None => None,
+            };
+            unsafe {
+                CreateProcess(
+                    &mut child.hdl,
+                    base.as_ref()
+                        .map_or_else(crate::env::current_dir_handle, File::as_raw_handle),
+                    &path,
+                    &KCSlice::from_slice(&opts),
+                )
+            }
+        } else {
+            let path_dirs;
+            let search_dirs = match &self.search_dirs {
+                Some(dirs) => dirs.clone(),
+                None => {
+                    path_dirs = self.path_dirs();
+                    path_dirs.iter().map(File::as_raw_handle).collect()
+                }
+            };
+
+            let mut res = DOES_NOT_EXIST;
+            for dir in search_dirs {
+                res = unsafe {
+                    CreateProcess(&mut child.hdl, dir, &path, &KCSlice::from_slice(&opts))
+                };
+                if res >= 0 {
+                    break;
+                }
+            }
+            res
+        };
+
+        drop(child_ends);
+
+        if res < 0 {
+            Err(Error::from_raw_os_error(res))
+        } else {
+            child.joined = false;
+            Ok(child)
+        }
+    }
+
+    /// Spawns the child and waits for it to exit.
+    pub fn status(&mut self) -> io::Result<ExitStatus> {
+        self.spawn()?.wait()
+    }
+
+    /// Spawns the child, collects its output and waits for it to exit.
+    ///
+    /// Unless configured otherwise, the child's stdout is captured and its stderr is inherited.
+    /// Pipes can't be read without blocking, so stdout is read to EOF before stderr,
+    /// and a child whose piped stderr fills up first will block.
+    pub fn output(&mut self) -> io::Result<Output> {
+        if self.stdout.is_none() {
+            self.stdout = Some(Stdio::piped());
+        }
+
+        let mut child = self.spawn()?;
+        drop(child.stdin.take());
+
+        let mut stdout = Vec::new();
+        if let Some(mut pipe) = child.stdout.take() {
+            ReadEx::read_to_end(&mut pipe, &mut stdout)?;
+        }
+
+        let mut stderr = Vec::new();
+        if let Some(mut pipe) = child.stderr.take() {
+            ReadEx::read_to_end(&mut pipe, &mut stderr)?;
+        }
+
+        let status = child.wait()?;
+
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// The environment of the child, as `KEY=VALUE` strings.
+    fn build_env(&self) -> Vec<String> {
+        let mut vars = Vec::new();
+
+        if !self.env_clear {
+            for (key, val) in crate::start::vars() {
+                if !self.env.iter().any(|(k, _)| *k == key) {
+                    vars.push(format!("{key}={val}"));
+                }
+            }
+        }
+
+        for (key, val) in &self.env {
+            if let Some(val) = val {
+                vars.push(format!("{key}={val}"));
+            }
+        }
+
+        if let Some(dir) = &self.current_dir {
+            vars.retain(|var| !var.starts_with("PWD="));
+            if let Some(dir) = crate::env::absolute(dir) {
+                vars.push(format!("PWD={dir}"));
+            }
+        }
+
+        vars
+    }
+
+    /// Opens the directories listed in the `PATH` that the child would receive.
+    fn path_dirs(&self) -> Vec<File> {
+        let path = match self.env.iter().find(|(k, _)| k == "PATH") {
+            Some((_, val)) => val.as_deref().map(Cow::Borrowed),
+            None if self.env_clear => None,
+            None => crate::start::var("PATH"),
+        };
+
+        path.as_deref()
+            .into_iter()
+            .flat_map(|path| path.split(':'))
+            .filter_map(|dir| OpenOptions::new().read(true).directory(true).open(dir).ok())
+            .collect()
+    }
+}
+
+/// Configures one of the standard I/O handles of a child process.
+pub struct Stdio(StdioKind);
+
+enum StdioKind {
+    Inherit,
+    Piped,
+    File(File),
+    PipeReader(PipeReader),
+    PipeWriter(PipeWriter),
+    Raw(HandlePtr<IOHandle>),
+}
+
+impl StdioKind {
+    fn raw_handle(&self) -> HandlePtr<IOHandle> {
+        match self {
+            StdioKind::Inherit | StdioKind::Piped => HandlePtr::null(),
+            StdioKind::File(file) => file.as_raw_handle().cast(),
+            StdioKind::PipeReader(pipe) => pipe.as_raw_handle(),
+            StdioKind::PipeWriter(pipe) => pipe.as_raw_handle(),
+            StdioKind::Raw(hdl) => *hdl,
+        }
+    }
+}
+
+impl Stdio {
+    /// Uses the same handle as the current process.
+    pub const fn inherit() -> Self {
+        Self(StdioKind::Inherit)
+    }
+
+    /// Connects the handle to a new pipe, the other end of which is stored in the [`Child`].
+    pub const fn piped() -> Self {
+        Self(StdioKind::Piped)
+    }
+
+    /// Uses `hdl`, which is borrowed rather than closed once the child is spawned.
+    ///
+    /// # Safety
+    /// `hdl` must be a valid IO handle, and must remain open until the child is spawned.
+    pub const unsafe fn from_raw_handle(hdl: HandlePtr<IOHandle>) -> Self {
+        Self(StdioKind::Raw(hdl))
+    }
+}
+
+impl From<File> for Stdio {
+    fn from(value: File) -> Self {
+        Self(StdioKind::File(value))
+    }
+}
+
+impl From<PipeReader> for Stdio {
+    fn from(value: PipeReader) -> Self {
+        Self(StdioKind::PipeReader(value))
+    }
+}
+
+impl From<PipeWriter> for Stdio {
+    fn from(value: PipeWriter) -> Self {
+        Self(StdioKind::PipeWriter(value))
+    }
+}
+
+/// A spawned child process.
+///
+/// Dropping a `Child` without waiting for it detaches it, which lets it keep running.
+pub struct Child {
+    hdl: HandlePtr<ProcessHandle>,
+    joined: bool,
+    /// The write end of the child's stdin, if it was [`Stdio::piped`].
+    pub stdin: Option<PipeWriter>,
+    /// The read end of the child's stdout, if it was [`Stdio::piped`].
+    pub stdout: Option<PipeReader>,
+    /// The read end of the child's stderr, if it was [`Stdio::piped`].
+    pub stderr: Option<PipeReader>,
+}
+
+impl Child {
+    pub const fn as_raw_handle(&self) -> HandlePtr<ProcessHandle> {
+        self.hdl
+    }
+
+    /// The id of the child process.
+    pub fn id(&self) -> u64 {
+        process_id(self.hdl)
+    }
+
+    /// Waits for the child to exit.
+    ///
+    /// The child's stdin is closed first, so that it doesn't wait for input that never comes.
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        drop(self.stdin.take());
+
+        let mut status = bytemuck::zeroed();
+        let res = unsafe { JoinProcess(self.hdl, &mut status) };
+
+        if res < 0 {
+            Err(Error::from_raw_os_error(res))
+        } else {
+            self.joined = true;
+            Ok(ExitStatus(status))
+        }
+    }
+
+    /// Checks whether the child has exited, without waiting for it.
+    ///
+    /// Returns `None` if the child is still running.
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        let mut status = bytemuck::zeroed();
+        let res = unsafe { TryJoinProcess(self.hdl, &mut status) };
+
+        if res == WOULD_BLOCK {
+            Ok(None)
+        } else if res < 0 {
+            Err(Error::from_raw_os_error(res))
+        } else {
+            self.joined = true;
+            Ok(Some(ExitStatus(status)))
+        }
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        if !self.joined {
+            unsafe {
+                DetachProcess(self.hdl);
+            }
+        }
+    }
+}
+
+/// The result of [`Command::output`].
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    /// Empty unless stderr was set to [`Stdio::piped`].
+    pub stderr: Vec<u8>,
+}
+
+/// How a child process finished, as returned by [`Child::wait`].
+#[derive(Copy, Clone)]
+pub struct ExitStatus(JoinStatus);
+
+impl ExitStatus {
+    pub const fn from_raw(status: JoinStatus) -> Self {
+        Self(status)
+    }
+
+    pub const fn into_raw(self) -> JoinStatus {
+        self.0
+    }
+
+    /// The exit code, if the process exited normally.
+    pub const fn code(&self) -> Option<i32> {
+        if self.0.kind == JOIN_STATUS_EXITED {
+            Some(self.0.exit_code)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the process exited normally with code `0`.
+    pub const fn success(&self) -> bool {
+        matches!(self.code(), Some(0))
+    }
+
+    /// The exception that terminated the process, if it was terminated by an unmanaged exception.
+    pub const fn exception(&self) -> Option<&ExceptionStatusInfo> {
+        if self.0.kind == JOIN_STATUS_EXCEPTION {
+            Some(&self.0.exception)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the process was killed, rather than exiting or raising an exception.
+    pub const fn killed(&self) -> bool {
+        self.0.kind == JOIN_STATUS_KILLED
+    }
+}
+
+impl core::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if let Some(code) = self.code() {
+            f.write_fmt(format_args!("exit code: {code}"))
+        } else if let Some(except) = self.exception() {
+            f.write_fmt(format_args!(
+                "unmanaged exception: {} ({:#x})",
+                except.except_code, except.except_info
+            ))
+        } else if self.killed() {
+            f.write_str("killed")
+        } else {
+            f.write_fmt(format_args!("unknown status {:#x}", self.0.kind))
+        }
+    }
+}
+
+impl core::fmt::Debug for ExitStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_fmt(format_args!("ExitStatus({self})"))
+    }
+}
+
+/// Exits with the same code as the child, or raises the same exception if it raised one.
+impl Termination for ExitStatus {
+    fn report(self) -> i32 {
+        if let Some(code) = self.code() {
+            code
+        } else if let Some(except) = self.exception() {
+            let _ = genio::Write::flush(&mut io::stdout());
+            unsafe { UnmanagedException(except) }
+        } else {
+            eprintln!("child process {self}");
+            -1
+        }
+    }
+}
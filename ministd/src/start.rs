@@ -1,4 +1,9 @@
-use core::{cell::OnceCell, ffi::CStr};
+use core::{
+    cell::{OnceCell, RefCell},
+    ffi::CStr,
+};
+
+use alloc::{borrow::Cow, string::String, vec::Vec};
 
 use crate::{eprintln, helpers::AssertThreadSafe, println};
 
@@ -58,6 +63,10 @@ static ARGS: AssertThreadSafe<OnceCell<(usize, *mut *mut c_char)>> =
 static ENV: AssertThreadSafe<OnceCell<*mut *mut c_char>> =
     unsafe { AssertThreadSafe::new_unchecked(OnceCell::new()) };
 
+/// Variables set or removed by [`set_var`] and [`remove_var`], which take precedence over `ENV`.
+static ENV_OVERRIDES: AssertThreadSafe<RefCell<Vec<(String, Option<String>)>>> =
+    unsafe { AssertThreadSafe::new_unchecked(RefCell::new(Vec::new())) };
+
 #[doc(hidden)]
 pub use core::ffi::c_char;
 
@@ -73,7 +82,9 @@ pub unsafe fn rt_start<R: Termination>(
     if argc > 0 {
         let _ = PRG_NAME.set(unsafe { CStr::from_ptr(argv.read()) });
     }
-    Termination::report(main())
+    let res = main();
+    let _ = genio::Write::flush(&mut crate::io::stdout());
+    Termination::report(res)
 }
 
 #[macro_export]
@@ -92,35 +103,87 @@ macro_rules! def_main {
     };
 }
 
-pub struct Vars(*mut *mut c_char);
+pub struct Vars {
+    env: *mut *mut c_char,
+    overrides: usize,
+}
 
+/// The environment variables of the current process.
+///
+/// Variables from the environment the process started with are borrowed,
+/// and those set since then are copied, so that they can be changed while iterating.
 pub fn vars() -> Vars {
-    Vars(ENV.get().copied().unwrap())
+    Vars {
+        env: ENV.get().copied().unwrap(),
+        overrides: 0,
+    }
+}
+
+/// Looks up `var` in the variables set or removed since the process started.
+fn overridden(var: &str) -> Option<Option<String>> {
+    ENV_OVERRIDES
+        .borrow()
+        .iter()
+        .find_map(|(key, val)| (key == var).then(|| val.clone()))
 }
 
 impl Iterator for Vars {
-    type Item = (&'static str, &'static str);
+    type Item = (Cow<'static, str>, Cow<'static, str>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let val = unsafe { self.0.read() };
-        if val.is_null() {
-            return None;
-        }
-        self.0 = unsafe { self.0.add(1) };
+        loop {
+            let val = unsafe { self.env.read() };
+            if val.is_null() {
+                break;
+            }
+            self.env = unsafe { self.env.add(1) };
 
-        let cstr = unsafe { CStr::from_ptr(val) };
-        let bytes = cstr.to_bytes();
+            let cstr = unsafe { CStr::from_ptr(val) };
+            let bytes = cstr.to_bytes();
 
-        let str = unsafe { core::str::from_utf8_unchecked(bytes) };
+            let str = unsafe { core::str::from_utf8_unchecked(bytes) };
+
+            let (var, val) = str.split_once('=').unwrap();
 
-        let (var, val) = str.split_once('=').unwrap();
+            if overridden(var).is_none() {
+                return Some((Cow::Borrowed(var), Cow::Borrowed(val)));
+            }
+        }
+
+        let overrides = ENV_OVERRIDES.borrow();
+        while let Some((var, val)) = overrides.get(self.overrides) {
+            self.overrides += 1;
+            if let Some(val) = val {
+                return Some((Cow::Owned(var.clone()), Cow::Owned(val.clone())));
+            }
+        }
+        None
+    }
+}
 
-        Some((var, val))
+pub fn var(var: &str) -> Option<Cow<'static, str>> {
+    match overridden(var) {
+        Some(val) => val.map(Cow::Owned),
+        None => vars().find_map(|(key, val)| (key == var).then_some(val)),
     }
 }
 
-pub fn var(var: &str) -> Option<&'static str> {
-    vars().find_map(|(key, val)| (key == var).then_some(val))
+/// Sets the environment variable `key` of the current process, which is inherited by the processes it spawns.
+pub fn set_var(key: &str, val: &str) {
+    set_override(key, Some(String::from(val)));
+}
+
+/// Removes the environment variable `key` of the current process.
+pub fn remove_var(key: &str) {
+    set_override(key, None);
+}
+
+fn set_override(key: &str, val: Option<String>) {
+    let mut overrides = ENV_OVERRIDES.borrow_mut();
+    match overrides.iter_mut().find(|(k, _)| k == key) {
+        Some((_, v)) => *v = val,
+        None => overrides.push((String::from(key), val)),
+    }
 }
 
 pub struct Args(*mut *mut c_char, *mut *mut c_char);